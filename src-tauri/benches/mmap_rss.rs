@@ -0,0 +1,71 @@
+//! Peak memory of encrypting a 512 MiB file read whole, streamed through a
+//! buffer, and streamed from a memory map. Each runs in its own process, as
+//! the peak can't be reset. Linux only, since it reads `/proc/self/status`.
+//!
+//! ```text
+//! cargo bench --bench mmap_rss
+//! ```
+
+use cryptit_lib::file_ops::{self, EncryptOptions};
+use std::fs;
+use std::io::Write;
+use std::process::Command;
+
+const DATA_LEN: usize = 512 * 1024 * 1024;
+const MODES: [&str; 3] = ["whole", "buffered", "mapped"];
+
+/// A field of `/proc/self/status` in KiB, e.g. `VmHWM`.
+fn status_kib(field: &str) -> Option<u64> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with(&format!("{}:", field)))?;
+    line.split_whitespace().nth(1)?.parse().ok()
+}
+
+fn run(mode: &str) {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("input.bin");
+    // Written in pieces so the input itself doesn't raise the peak
+    let mut file = fs::File::create(&input).unwrap();
+    let piece = vec![0x5au8; 1024 * 1024];
+    for _ in 0..DATA_LEN / piece.len() {
+        file.write_all(&piece).unwrap();
+    }
+    drop(file);
+    let options = match mode {
+        "whole" => EncryptOptions {
+            stream_threshold: u64::MAX,
+            ..Default::default()
+        },
+        "buffered" => EncryptOptions {
+            mmap_threshold: u64::MAX,
+            ..Default::default()
+        },
+        _ => EncryptOptions::default(),
+    };
+
+    let before = (status_kib("RssAnon"), status_kib("RssFile"));
+    let input = input.to_str().unwrap();
+    let output_dir = dir.path().to_str().unwrap();
+    file_ops::encrypt_file(input, output_dir, 2, 3, &[], &options, &mut |_| {}).unwrap();
+    let after = (status_kib("RssAnon"), status_kib("RssFile"));
+    println!(
+        "{:>8}: RssAnon {:?} -> {:?} KiB, RssFile {:?} -> {:?} KiB, peak {:?} KiB",
+        mode,
+        before.0,
+        after.0,
+        before.1,
+        after.1,
+        status_kib("VmHWM")
+    );
+}
+
+fn main() {
+    if let Some(mode) = std::env::args().skip(1).find(|arg| MODES.contains(&arg.as_str())) {
+        return run(&mode);
+    }
+    let exe = std::env::current_exe().unwrap();
+    for mode in MODES {
+        let status = Command::new(&exe).arg(mode).status().unwrap();
+        assert!(status.success(), "{} run failed", mode);
+    }
+}
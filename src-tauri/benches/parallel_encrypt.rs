@@ -0,0 +1,28 @@
+//! Chunked encryption of 1 GiB on one thread and in parallel.
+//!
+//! ```text
+//! cargo bench --bench parallel_encrypt
+//! ```
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use cryptit_lib::crypto::{encrypt_data_parallel, EncryptionKey, DEFAULT_CHUNK_SIZE};
+
+const DATA_LEN: usize = 1024 * 1024 * 1024;
+
+fn parallel_encrypt(c: &mut Criterion) {
+    let data = vec![0x5au8; DATA_LEN];
+    let key = EncryptionKey::generate();
+
+    let mut group = c.benchmark_group("encrypt_data_parallel");
+    group.throughput(Throughput::Bytes(DATA_LEN as u64));
+    group.sample_size(10);
+    for threads in [1, 4, 8] {
+        group.bench_with_input(BenchmarkId::from_parameter(threads), &threads, |b, &threads| {
+            b.iter(|| encrypt_data_parallel(&data, &key, DEFAULT_CHUNK_SIZE, threads).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, parallel_encrypt);
+criterion_main!(benches);
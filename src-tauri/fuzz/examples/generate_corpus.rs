@@ -0,0 +1,78 @@
+//! Writes valid encrypted files and shares to `corpus/` as seeds for the
+//! fuzz targets: `cargo run --example generate_corpus` from this directory.
+
+use std::fs;
+use std::path::Path;
+
+use cryptit_lib::crypto::{CipherAlgorithm, PasswordKdf};
+use cryptit_lib::file_ops::{self, EncryptOptions};
+
+fn main() {
+    let corpus = Path::new(env!("CARGO_MANIFEST_DIR")).join("corpus");
+    let files = corpus.join("parse_file");
+    let shares = corpus.join("reconstruct_secret");
+    fs::create_dir_all(&files).expect("create corpus directory");
+    fs::create_dir_all(&shares).expect("create corpus directory");
+
+    let scratch = tempfile::tempdir().expect("create scratch directory");
+    let input = scratch.path().join("input.txt");
+    fs::write(&input, b"fuzzing seed plaintext").expect("write input");
+    let input = input.to_str().expect("UTF-8 temp path");
+    let output_dir = scratch.path().to_str().expect("UTF-8 temp path");
+
+    let variants = [
+        ("aes", EncryptOptions::default()),
+        (
+            "chacha",
+            EncryptOptions {
+                cipher: CipherAlgorithm::ChaCha20Poly1305,
+                ..Default::default()
+            },
+        ),
+        (
+            "xchacha-chunked",
+            EncryptOptions {
+                cipher: CipherAlgorithm::XChaCha20Poly1305,
+                stream_threshold: 0,
+                ..Default::default()
+            },
+        ),
+        (
+            "compressed",
+            EncryptOptions {
+                compress: true,
+                comment: Some("seed".to_string()),
+                context: Some("fuzz".to_string()),
+                ..Default::default()
+            },
+        ),
+        (
+            "armored",
+            EncryptOptions {
+                armor: true,
+                ..Default::default()
+            },
+        ),
+    ];
+    for (name, options) in variants {
+        let options = EncryptOptions {
+            output_name: Some(format!("{}.cryptit", name)),
+            ..options
+        };
+        let result = file_ops::encrypt_file(input, output_dir, 2, 3, &[], &options, &mut |_| {})
+            .expect("encrypt seed file");
+        fs::copy(&result.encrypted_file_path, files.join(name)).expect("copy seed file");
+        fs::write(shares.join(name), result.shares.join("\n")).expect("write seed shares");
+    }
+
+    let options = EncryptOptions {
+        output_name: Some("password.cryptit".to_string()),
+        ..Default::default()
+    };
+    let result =
+        file_ops::encrypt_file_with_password(input, output_dir, "seed", PasswordKdf::Pbkdf2, &options, &mut |_| {})
+            .expect("encrypt seed file");
+    fs::copy(&result.encrypted_file_path, files.join("password")).expect("copy seed file");
+
+    println!("Wrote seeds to {}", corpus.display());
+}
@@ -0,0 +1,33 @@
+#![no_main]
+
+use cryptit_lib::crypto::{self, CipherAlgorithm, CryptoError, EncryptedData, EncryptionKey};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // A nonce of AES-GCM's length followed by the ciphertext, as a file
+    // holds them; shorter inputs become a short nonce
+    let (nonce, ciphertext) = data.split_at(data.len().min(12));
+    let encrypted = EncryptedData {
+        nonce: nonce.to_vec(),
+        ciphertext: ciphertext.to_vec(),
+    };
+    let key = EncryptionKey::generate();
+    for algorithm in [
+        CipherAlgorithm::Aes256Gcm,
+        CipherAlgorithm::ChaCha20Poly1305,
+        CipherAlgorithm::Aes256GcmSiv,
+        CipherAlgorithm::XChaCha20Poly1305,
+    ] {
+        let key = EncryptionKey::from_bytes(key.as_bytes()).expect("32-byte key").with_algorithm(algorithm);
+        match crypto::decrypt_data(&encrypted, &key) {
+            // A forgery under a key the fuzzer never sees would be a finding
+            Ok(_) => panic!("{} accepted data not encrypted under the key", algorithm.name()),
+            Err(
+                CryptoError::AuthenticationFailed
+                | CryptoError::TruncatedData { .. }
+                | CryptoError::InvalidNonceLength { .. },
+            ) => {}
+            Err(e) => panic!("unexpected error from {}: {:?}", algorithm.name(), e),
+        }
+    }
+});
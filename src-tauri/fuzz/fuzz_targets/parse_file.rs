@@ -0,0 +1,24 @@
+#![no_main]
+
+use cryptit_lib::format::{self, Container, FileHeader, Trailer};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // Every way an encrypted file's bytes are parsed before decryption
+    if let Ok((header, offset)) = FileHeader::from_bytes(data) {
+        assert!(offset <= data.len());
+        // Whatever parses has to survive being written back out
+        let (reparsed, _) = FileHeader::from_bytes(&header.to_bytes()).expect("re-encoded header parses");
+        assert_eq!(reparsed, header);
+    }
+    if let Some(trailer) = Trailer::parse(data) {
+        assert!(trailer.encoded_len() <= data.len());
+    }
+    if let Ok(Container::Current { ciphertext, .. }) = format::parse_container(data) {
+        assert!(ciphertext.len() <= data.len());
+    }
+    let _ = format::parse_encrypted_file(data);
+    if format::is_armored(data) {
+        let _ = format::dearmor(data).and_then(|bytes| format::parse_container(&bytes).map(|_| ()));
+    }
+});
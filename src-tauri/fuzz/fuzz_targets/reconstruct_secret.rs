@@ -0,0 +1,35 @@
+#![no_main]
+
+use base64::{engine::general_purpose, Engine};
+use cryptit_lib::sss;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // One share per line, as pasted into the app
+    let text = String::from_utf8_lossy(data);
+    let shares: Vec<String> = text.lines().map(str::to_string).collect();
+    if let Ok(decoded) = sss::decode_shares(&shares) {
+        let _ = sss::reconstruct_secret(&decoded);
+    }
+
+    // Arbitrary bytes in well-formed base64, a share's worth of key and MAC
+    // each: bare, so they reach the combine, and with metadata, where the
+    // MAC check has to turn them away
+    let raw: Vec<String> = data.chunks(65).map(|chunk| general_purpose::STANDARD.encode(chunk)).collect();
+    if let Ok(bare) = sss::decode_shares(&raw) {
+        let _ = sss::reconstruct_secret(&bare);
+    }
+    let labeled: Vec<String> = raw
+        .iter()
+        .enumerate()
+        .map(|(i, data)| format!("CSHR1:{}:2:{}:fuzz:{}", i + 1, raw.len(), data))
+        .collect();
+    if let Ok(shares) = sss::decode_shares(&labeled) {
+        let _ = sss::reconstruct_secret(&shares);
+    }
+
+    // The same bytes as a binary .share file
+    if let Ok(share) = sss::decode_share_file(data) {
+        let _ = sss::reconstruct_secret(&[share]);
+    }
+});
@@ -0,0 +1,192 @@
+//! Command-line front end to the same operations as the desktop app, for
+//! scripts, CI pipelines and machines without a display.
+
+use base64::{engine::general_purpose, Engine};
+use clap::{Parser, Subcommand};
+use cryptit_lib::file_ops::{self, DecryptOptions, EncryptOptions};
+use cryptit_lib::sss;
+use cryptit_lib::AppError;
+use std::fs;
+use std::process::ExitCode;
+use zeroize::Zeroizing;
+
+#[derive(Parser)]
+#[command(name = "cryptit-cli", version, about = "Encrypt files and split their keys with Shamir secret sharing")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Encrypt a file and print its key shares, one per line
+    Encrypt {
+        #[arg(long)]
+        input: String,
+        #[arg(long)]
+        output_dir: String,
+        /// Shares needed to decrypt
+        #[arg(short = 'k', long)]
+        threshold: u8,
+        /// Shares to generate
+        #[arg(short = 'n', long)]
+        shares: u8,
+        /// Write base64 text instead of binary
+        #[arg(long)]
+        armor: bool,
+        /// Compress with zstd first, unless that doesn't make it smaller
+        #[arg(long)]
+        compress: bool,
+        /// Bind the file to this string; decrypting needs it again
+        #[arg(long)]
+        context: Option<String>,
+        /// A note stored unencrypted in the header
+        #[arg(long)]
+        comment: Option<String>,
+        /// File name to write instead of {stem}.cryptit
+        #[arg(long)]
+        output_name: Option<String>,
+        /// Replace the output file if it already exists
+        #[arg(long)]
+        overwrite: bool,
+        /// Also write {output}.sha256 for sha256sum -c
+        #[arg(long)]
+        checksum: bool,
+    },
+    /// Decrypt a file with its key shares
+    Decrypt {
+        #[arg(long)]
+        input: String,
+        #[arg(long)]
+        output_dir: String,
+        /// A key share; repeat for each share
+        #[arg(long = "share", required = true)]
+        shares: Vec<String>,
+        /// The context the file was bound to, if any
+        #[arg(long)]
+        context: Option<String>,
+        /// File name to write instead of the original one
+        #[arg(long)]
+        output_name: Option<String>,
+        /// Replace the output file if it already exists
+        #[arg(long)]
+        overwrite: bool,
+        /// Also write {output}.sha256 for sha256sum -c
+        #[arg(long)]
+        checksum: bool,
+    },
+    /// Split a base64 key into shares, printed one per line
+    SplitKey {
+        #[arg(long)]
+        key: String,
+        #[arg(short = 'k', long)]
+        threshold: u8,
+        #[arg(short = 'n', long)]
+        shares: u8,
+    },
+    /// Combine shares back into a key, printed as base64
+    CombineKey {
+        #[arg(long = "share", required = true)]
+        shares: Vec<String>,
+    },
+}
+
+fn main() -> ExitCode {
+    match run(Cli::parse().command) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(command: Command) -> Result<(), AppError> {
+    match command {
+        Command::Encrypt {
+            input,
+            output_dir,
+            threshold,
+            shares,
+            armor,
+            compress,
+            context,
+            comment,
+            output_name,
+            overwrite,
+            checksum,
+        } => {
+            create_output_dir(&output_dir)?;
+            let options = EncryptOptions {
+                armor,
+                compress,
+                context,
+                comment,
+                output_name,
+                overwrite_policy: overwrite.into(),
+                checksum_file: checksum,
+                ..Default::default()
+            };
+            let result = file_ops::encrypt_file(
+                &input,
+                &output_dir,
+                threshold,
+                shares,
+                &[],
+                &options,
+                &mut |_| {},
+            )?;
+            eprintln!("Encrypted to {}", result.encrypted_file_path);
+            for share in result.shares {
+                println!("{}", share);
+            }
+        }
+        Command::Decrypt {
+            input,
+            output_dir,
+            shares,
+            context,
+            output_name,
+            overwrite,
+            checksum,
+        } => {
+            create_output_dir(&output_dir)?;
+            let options = DecryptOptions {
+                context,
+                output_name,
+                overwrite,
+                checksum_file: checksum,
+            };
+            let result = file_ops::decrypt_file(&input, &output_dir, &shares, &options, &mut |_| {})?;
+            eprintln!("Decrypted to {} (SHA-256: {})", result.output_path, result.sha256);
+            if let Some(comment) = result.comment {
+                eprintln!("Comment: {}", comment);
+            }
+            if let Some(warning) = result.warning {
+                eprintln!("warning: {}", warning);
+            }
+        }
+        Command::SplitKey { key, threshold, shares } => {
+            let key = Zeroizing::new(
+                general_purpose::STANDARD
+                    .decode(key.trim())
+                    .map_err(|e| AppError::Crypto(format!("Key is not valid base64: {}", e)))?,
+            );
+            for share in sss::split_secret(&key, threshold, shares)? {
+                println!("{}", share.encode());
+            }
+        }
+        Command::CombineKey { shares } => {
+            let shares = sss::validate_shares(&shares)?;
+            let key = sss::reconstruct_secret(&shares)?;
+            println!("{}", general_purpose::STANDARD.encode(&key));
+        }
+    }
+    Ok(())
+}
+
+/// Unlike the dialog in the app, a path on the command line may not exist yet.
+fn create_output_dir(output_dir: &str) -> Result<(), AppError> {
+    fs::create_dir_all(output_dir)
+        .map_err(|e| AppError::Io(format!("Failed to create directory {}: {}", output_dir, e)))
+}
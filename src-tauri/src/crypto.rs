@@ -1,10 +1,24 @@
 use aes_gcm::{
-    aead::{Aead, AeadCore, KeyInit, OsRng},
-    Aes256Gcm, Nonce,
+    aead::{generic_array::typenum::Unsigned, Aead, AeadCore, KeyInit, Nonce, OsRng, Payload},
+    Aes256Gcm,
 };
-use rand::RngCore;
+use aes_gcm_siv::Aes256GcmSiv;
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::{ChaCha20Poly1305, XChaCha20Poly1305};
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use pbkdf2::pbkdf2_hmac;
+use rand::{CryptoRng, RngCore};
+use rayon::prelude::*;
+use sha2::{Digest, Sha256, Sha512};
+use std::collections::HashSet;
+use std::io::{self, Read, Write};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
 use thiserror::Error;
-use zeroize::Zeroize;
+use x25519_dalek::{EphemeralSecret, PublicKey, StaticSecret};
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
 
 #[derive(Error, Debug)]
 pub enum CryptoError {
@@ -12,12 +26,373 @@ pub enum CryptoError {
     EncryptionFailed,
     #[error("Decryption failed")]
     DecryptionFailed,
+    /// The tag didn't verify: wrong key, modified ciphertext, or associated
+    /// data that differs from what was bound at encryption time.
+    #[error("Authentication failed: the data was modified or does not belong here")]
+    AuthenticationFailed,
+    /// Too short to even hold the authentication tag.
+    #[error("Encrypted data is incomplete: {actual} bytes, at least {minimum} needed")]
+    TruncatedData { actual: usize, minimum: usize },
     #[error("Invalid key length")]
     InvalidKeyLength,
+    #[error("Key is not valid hex")]
+    InvalidKeyEncoding,
+    #[error("Nonce is {actual} bytes long; this cipher takes {expected}")]
+    InvalidNonceLength { expected: usize, actual: usize },
+    #[error("Unsupported cipher: {0}")]
+    UnsupportedCipher(String),
+    #[error("Unsupported key derivation function: {0}")]
+    UnsupportedKdf(String),
+    #[error("Key derivation failed: {0}")]
+    KeyDerivationFailed(String),
+    #[error("Key derivation parameters are too weak: {0}")]
+    WeakKdfParameters(String),
+    #[error("Chunk {0} is corrupted or has been tampered with")]
+    ChunkCorrupted(u32),
+    #[error("Encrypted data ends early: chunks are missing")]
+    StreamTruncated,
+    #[error("Keyfile is empty")]
+    EmptyKeyfile,
+    /// The recipient public key is a low-order point, which would make the
+    /// shared secret predictable.
+    #[error("Invalid recipient public key")]
+    InvalidPublicKey,
+    #[error("Invalid signing key")]
+    InvalidSigningKey,
+    /// The signature wasn't made by the signer's key over this data.
+    #[error("Signature does not verify")]
+    BadSignature,
+    #[error("Compression failed: {0}")]
+    CompressionFailed(io::Error),
+    #[error("Decompression failed: {0}")]
+    DecompressionFailed(io::Error),
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+}
+
+const HEX_DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+/// Authentication tag appended to the ciphertext. Every cipher uses 16 bytes.
+pub const TAG_LEN: usize = 16;
+
+pub const SALT_LEN: usize = 16;
+
+/// Length of [`EncryptionKey::check_value`].
+pub const KEY_CHECK_LEN: usize = 8;
+
+/// Plaintext bytes per chunk when streaming.
+pub const DEFAULT_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// Each streamed chunk is written as a `u32` length prefix followed by the
+/// ciphertext and its tag.
+pub const CHUNK_OVERHEAD: u64 = 4 + TAG_LEN as u64;
+
+/// Length of a key wrapped by [`wrap_key`]: nonce, key and tag.
+pub const WRAPPED_KEY_LEN: usize = 12 + 32 + TAG_LEN;
+
+/// Associated data of [`wrap_key`], so a wrapped key can't pass for any
+/// other AES-GCM message under the same key.
+const KEY_WRAP_AAD: &[u8] = b"CryptIt key wrap v1";
+
+/// Length of [`hash_keyfile`]'s digest.
+pub const KEYFILE_DIGEST_LEN: usize = 64;
+
+/// HKDF info of a key derived from a keyfile alone, and from a keyfile and
+/// a shared secret, so the two can never produce the same key.
+const KEYFILE_INFO: &[u8] = b"CryptIt keyfile v1";
+const KEYFILE_AND_SHARES_INFO: &[u8] = b"CryptIt keyfile and shares v1";
+
+/// Length of an X25519 public or private key.
+pub const X25519_KEY_LEN: usize = 32;
+
+/// Length of a key sealed by [`seal_key_to_recipient`]: the ephemeral public
+/// key followed by the wrapped file key.
+pub const RECIPIENT_SLOT_LEN: usize = X25519_KEY_LEN + WRAPPED_KEY_LEN;
+
+/// HKDF info of the key that wraps a file key for a recipient.
+const RECIPIENT_INFO: &[u8] = b"CryptIt recipient v1";
+
+/// Length of an Ed25519 public or private key.
+pub const SIGNING_KEY_LEN: usize = 32;
+
+/// Length of an Ed25519 signature.
+pub const SIGNATURE_LEN: usize = 64;
+
+/// Prefixed to the digest that is signed, so a file signature can't be
+/// passed off as the signer's signature on anything else.
+const SIGNATURE_CONTEXT: &[u8] = b"CryptIt signature v1";
+
+/// Trailing nonce bytes replaced by the chunk index when streaming.
+pub const CHUNK_COUNTER_LEN: usize = 4;
+
+/// Fewest PBKDF2 iterations accepted, per the 2023 NIST SP 800-132 guidance
+/// for HMAC-SHA256. New files use exactly this many.
+pub const MIN_PBKDF2_ITERATIONS: u32 = 600_000;
+
+/// Password-based key derivation functions. Argon2id is preferred; PBKDF2 is
+/// there for compliance regimes (such as FIPS 140-2) that require it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PasswordKdf {
+    #[default]
+    Argon2id,
+    Pbkdf2,
+    Scrypt,
+}
+
+impl FromStr for PasswordKdf {
+    type Err = CryptoError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "argon2id" => Ok(PasswordKdf::Argon2id),
+            "pbkdf2" | "pbkdf2-sha256" => Ok(PasswordKdf::Pbkdf2),
+            "scrypt" => Ok(PasswordKdf::Scrypt),
+            _ => Err(CryptoError::UnsupportedKdf(s.to_string())),
+        }
+    }
+}
+
+/// scrypt cost parameters (RFC 7914).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScryptParams {
+    /// CPU/memory cost; a power of two. Memory use is `128 * n * r` bytes.
+    pub n: u64,
+    /// Block size
+    pub r: u32,
+    /// Parallelism
+    pub p: u32,
+}
+
+impl ScryptParams {
+    /// N = 2^14, r = 8, p = 1: 16 MiB, for keys derived while a user waits.
+    pub fn interactive() -> Self {
+        Self { n: 1 << 14, r: 8, p: 1 }
+    }
+
+    /// N = 2^20, r = 8, p = 1: 1 GiB, for keys worth a much longer wait.
+    pub fn sensitive() -> Self {
+        Self { n: 1 << 20, r: 8, p: 1 }
+    }
+
+    /// Checks the RFC 7914 constraints: `n` a power of two greater than 1,
+    /// `r` and `p` nonzero, and `n * r * p` below 2^32.
+    fn check(&self) -> Result<(), CryptoError> {
+        if self.n < 2 || !self.n.is_power_of_two() {
+            return Err(CryptoError::WeakKdfParameters(format!(
+                "scrypt N must be a power of two greater than 1, got {}",
+                self.n
+            )));
+        }
+        let cost = self.n as u128 * self.r as u128 * self.p as u128;
+        if cost == 0 || cost >= 1 << 32 {
+            return Err(CryptoError::WeakKdfParameters(format!(
+                "scrypt needs nonzero r and p with N * r * p below 2^32, got N = {}, r = {}, p = {}",
+                self.n, self.r, self.p
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Argon2id cost parameters. The defaults follow the OWASP recommendation of
+/// 19 MiB of memory, two passes and one lane.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Argon2Params {
+    /// Memory cost in KiB
+    pub m_cost: u32,
+    /// Number of passes over memory
+    pub t_cost: u32,
+    /// Degree of parallelism
+    pub p_cost: u32,
+}
+
+impl Default for Argon2Params {
+    fn default() -> Self {
+        Self {
+            m_cost: 19 * 1024,
+            t_cost: 2,
+            p_cost: 1,
+        }
+    }
+}
+
+impl Argon2Params {
+    /// Checks parameters chosen for a new file: none below the defaults,
+    /// and within what Argon2 allows. Files written with weaker parameters
+    /// still decrypt.
+    pub fn check(&self) -> Result<(), CryptoError> {
+        let min = Self::default();
+        if self.m_cost < min.m_cost || self.t_cost < min.t_cost || self.p_cost < min.p_cost {
+            return Err(CryptoError::WeakKdfParameters(format!(
+                "Argon2id needs at least {} KiB of memory, {} passes and {} lane, got {} KiB, {} and {}",
+                min.m_cost, min.t_cost, min.p_cost, self.m_cost, self.t_cost, self.p_cost
+            )));
+        }
+        Params::new(self.m_cost, self.t_cost, self.p_cost, Some(32))
+            .map_err(|e| CryptoError::WeakKdfParameters(format!("Argon2id rejects these parameters: {}", e)))?;
+        Ok(())
+    }
+}
+
+/// Most memory [`calibrate_argon2`] will suggest, in KiB: 1 GiB.
+pub const MAX_CALIBRATED_M_COST: u32 = 1024 * 1024;
+
+/// Finds Argon2id parameters that take about `target` to derive a key on
+/// this machine, never below the defaults. Memory is raised first, since it
+/// costs an attacker the most, then the number of passes. Returns the
+/// parameters and the time they are expected to take.
+pub fn calibrate_argon2(target: Duration) -> Result<(Argon2Params, Duration), CryptoError> {
+    let time = |params: Argon2Params| {
+        let started = Instant::now();
+        EncryptionKey::from_password(b"calibration", &[0u8; SALT_LEN], params)?;
+        Ok::<_, CryptoError>(started.elapsed())
+    };
+
+    let mut params = Argon2Params::default();
+    let mut elapsed = time(params)?;
+    while elapsed * 2 <= target && params.m_cost * 2 <= MAX_CALIBRATED_M_COST {
+        params.m_cost *= 2;
+        elapsed = time(params)?;
+    }
+    // Time grows linearly with the passes, so this needs no more measuring
+    let passes = (params.t_cost as f64 * target.as_secs_f64() / elapsed.as_secs_f64().max(f64::EPSILON)) as u32;
+    if passes > params.t_cost {
+        elapsed = elapsed * passes / params.t_cost;
+        params.t_cost = passes;
+    }
+    Ok((params, elapsed))
+}
+
+pub fn generate_salt() -> [u8; SALT_LEN] {
+    generate_salt_with(&mut OsRng)
+}
+
+/// Like [`generate_salt`], drawing from `rng` instead of the OS.
+pub fn generate_salt_with(rng: &mut (impl RngCore + CryptoRng)) -> [u8; SALT_LEN] {
+    let mut salt = [0u8; SALT_LEN];
+    rng.fill_bytes(&mut salt);
+    salt
+}
+
+/// A random nonce of the length `algorithm` takes.
+pub fn generate_nonce(algorithm: CipherAlgorithm) -> Vec<u8> {
+    generate_nonce_with(algorithm, &mut OsRng)
+}
+
+/// Like [`generate_nonce`], drawing from `rng` instead of the OS.
+pub fn generate_nonce_with(algorithm: CipherAlgorithm, rng: &mut (impl RngCore + CryptoRng)) -> Vec<u8> {
+    let mut nonce = vec![0u8; algorithm.nonce_len()];
+    rng.fill_bytes(&mut nonce);
+    nonce
+}
+
+/// Hands out nonces for several files encrypted under one key, as
+/// [`generate_nonce_with`] does but never the same one twice. Chunked
+/// encryption overwrites the last [`CHUNK_COUNTER_LEN`] bytes with the chunk
+/// index, so nonces count as the same if the bytes before that match, and a
+/// repeat is drawn again. A counter would rule repeats out too, but it would
+/// have to be stored with the key so a later batch doesn't start again from
+/// zero; random nonces need nothing stored, and one batch is all that shares
+/// a key.
+#[derive(Debug, Default)]
+pub struct NonceTracker {
+    prefixes: HashSet<Vec<u8>>,
+}
+
+impl NonceTracker {
+    pub fn generate(&mut self, algorithm: CipherAlgorithm, rng: &mut (impl RngCore + CryptoRng)) -> Vec<u8> {
+        loop {
+            let nonce = generate_nonce_with(algorithm, rng);
+            if self.prefixes.insert(nonce[..nonce.len() - CHUNK_COUNTER_LEN].to_vec()) {
+                return nonce;
+            }
+        }
+    }
+}
+
+/// Supported AEAD ciphers. All take a 256-bit key; the nonce is 96 bits
+/// except for XChaCha20-Poly1305, see [`CipherAlgorithm::nonce_len`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CipherAlgorithm {
+    #[default]
+    Aes256Gcm,
+    /// Faster than AES on hardware without AES acceleration (many ARM and
+    /// RISC-V chips).
+    ChaCha20Poly1305,
+    /// Nonce-misuse resistant: a repeated nonce only reveals whether two
+    /// plaintexts are identical, where with AES-GCM it leaks their XOR and
+    /// the authentication key. Prefer it when one key encrypts many
+    /// messages, such as many small files under a single key, since random
+    /// 96-bit nonces then become likelier to collide. Encryption is a
+    /// little slower, as it makes two passes over the data.
+    Aes256GcmSiv,
+    /// ChaCha20-Poly1305 with a 192-bit nonce. Random nonces that long
+    /// never realistically collide, however many messages share a key. It
+    /// isn't the default because CryptIt releases before it can't open its
+    /// files.
+    XChaCha20Poly1305,
+}
+
+impl CipherAlgorithm {
+    /// Identifier stored in the `.cryptit` header.
+    pub fn id(self) -> u8 {
+        match self {
+            CipherAlgorithm::Aes256Gcm => 1,
+            CipherAlgorithm::ChaCha20Poly1305 => 2,
+            CipherAlgorithm::Aes256GcmSiv => 3,
+            CipherAlgorithm::XChaCha20Poly1305 => 4,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            CipherAlgorithm::Aes256Gcm => "aes256gcm",
+            CipherAlgorithm::ChaCha20Poly1305 => "chacha20",
+            CipherAlgorithm::Aes256GcmSiv => "aes256gcmsiv",
+            CipherAlgorithm::XChaCha20Poly1305 => "xchacha20",
+        }
+    }
+
+    /// Nonce length in bytes, which the header records alongside the nonce.
+    pub fn nonce_len(self) -> usize {
+        match self {
+            CipherAlgorithm::XChaCha20Poly1305 => 24,
+            _ => 12,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Result<Self, CryptoError> {
+        match id {
+            1 => Ok(CipherAlgorithm::Aes256Gcm),
+            2 => Ok(CipherAlgorithm::ChaCha20Poly1305),
+            3 => Ok(CipherAlgorithm::Aes256GcmSiv),
+            4 => Ok(CipherAlgorithm::XChaCha20Poly1305),
+            _ => Err(CryptoError::UnsupportedCipher(format!("id {}", id))),
+        }
+    }
+}
+
+impl FromStr for CipherAlgorithm {
+    type Err = CryptoError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "aes256gcm" | "aes-256-gcm" => Ok(CipherAlgorithm::Aes256Gcm),
+            "chacha20" | "chacha20poly1305" | "chacha20-poly1305" => {
+                Ok(CipherAlgorithm::ChaCha20Poly1305)
+            }
+            "aes256gcmsiv" | "aes-256-gcm-siv" => Ok(CipherAlgorithm::Aes256GcmSiv),
+            "xchacha20" | "xchacha20poly1305" | "xchacha20-poly1305" => {
+                Ok(CipherAlgorithm::XChaCha20Poly1305)
+            }
+            _ => Err(CryptoError::UnsupportedCipher(s.to_string())),
+        }
+    }
 }
 
 pub struct EncryptionKey {
-    key: [u8; 32], // 256-bit key for AES-256-GCM
+    key: [u8; 32], // 256-bit key, valid for every CipherAlgorithm
+    algorithm: CipherAlgorithm,
 }
 
 impl Drop for EncryptionKey {
@@ -28,9 +403,19 @@ impl Drop for EncryptionKey {
 
 impl EncryptionKey {
     pub fn generate() -> Self {
+        Self::generate_with(&mut OsRng)
+    }
+
+    /// Like [`EncryptionKey::generate`], drawing from `rng` instead of the
+    /// OS. A seeded `rng` makes the key reproducible, which is only wanted
+    /// for test fixtures.
+    pub fn generate_with(rng: &mut (impl RngCore + CryptoRng)) -> Self {
         let mut key = [0u8; 32];
-        OsRng.fill_bytes(&mut key);
-        Self { key }
+        rng.fill_bytes(&mut key);
+        Self {
+            key,
+            algorithm: CipherAlgorithm::default(),
+        }
     }
 
     pub fn from_bytes(bytes: &[u8]) -> Result<Self, CryptoError> {
@@ -39,54 +424,796 @@ impl EncryptionKey {
         }
         let mut key = [0u8; 32];
         key.copy_from_slice(bytes);
-        Ok(Self { key })
+        Ok(Self {
+            key,
+            algorithm: CipherAlgorithm::default(),
+        })
+    }
+
+    /// Derives a key from a password with Argon2id. The same password, salt
+    /// and parameters always produce the same key.
+    pub fn from_password(
+        password: &[u8],
+        salt: &[u8; SALT_LEN],
+        params: Argon2Params,
+    ) -> Result<Self, CryptoError> {
+        let params = Params::new(params.m_cost, params.t_cost, params.p_cost, Some(32))
+            .map_err(|e| CryptoError::KeyDerivationFailed(e.to_string()))?;
+        let argon2 = Argon2::new(Algorithm::Argon2id, Version::V0x13, params);
+
+        let mut key = [0u8; 32];
+        argon2
+            .hash_password_into(password, salt, &mut key)
+            .map_err(|e| CryptoError::KeyDerivationFailed(e.to_string()))?;
+        Ok(Self {
+            key,
+            algorithm: CipherAlgorithm::default(),
+        })
+    }
+
+    /// Derives a key from a password with PBKDF2-HMAC-SHA256. Fewer than
+    /// [`MIN_PBKDF2_ITERATIONS`] iterations are refused.
+    pub fn from_password_pbkdf2(
+        password: &[u8],
+        salt: &[u8; SALT_LEN],
+        iterations: u32,
+    ) -> Result<Self, CryptoError> {
+        if iterations < MIN_PBKDF2_ITERATIONS {
+            return Err(CryptoError::WeakKdfParameters(format!(
+                "PBKDF2 needs at least {} iterations, got {}",
+                MIN_PBKDF2_ITERATIONS, iterations
+            )));
+        }
+        let mut key = [0u8; 32];
+        pbkdf2_hmac::<Sha256>(password, salt, iterations, &mut key);
+        Ok(Self {
+            key,
+            algorithm: CipherAlgorithm::default(),
+        })
+    }
+
+    /// Derives a key from a password with scrypt. Parameters outside the
+    /// RFC 7914 constraints are refused.
+    pub fn from_password_scrypt(
+        password: &[u8],
+        salt: &[u8; SALT_LEN],
+        params: ScryptParams,
+    ) -> Result<Self, CryptoError> {
+        params.check()?;
+        let scrypt_params = scrypt::Params::new(params.n.trailing_zeros() as u8, params.r, params.p, 32)
+            .map_err(|e| CryptoError::KeyDerivationFailed(e.to_string()))?;
+        let mut key = [0u8; 32];
+        scrypt::scrypt(password, salt, &scrypt_params, &mut key)
+            .map_err(|e| CryptoError::KeyDerivationFailed(e.to_string()))?;
+        Ok(Self {
+            key,
+            algorithm: CipherAlgorithm::default(),
+        })
+    }
+
+    /// Derives a key with HKDF-SHA256 from a keyfile's [`hash_keyfile`]
+    /// digest and `salt`. With a `shared_secret`, e.g. one reconstructed from
+    /// shares, the key needs both it and the keyfile.
+    pub fn from_keyfile(
+        keyfile_digest: &[u8; KEYFILE_DIGEST_LEN],
+        shared_secret: Option<&[u8]>,
+        salt: &[u8; SALT_LEN],
+    ) -> Self {
+        let mut input = Zeroizing::new(keyfile_digest.to_vec());
+        let info = match shared_secret {
+            Some(secret) => {
+                input.extend_from_slice(secret);
+                KEYFILE_AND_SHARES_INFO
+            }
+            None => KEYFILE_INFO,
+        };
+        let mut key = [0u8; 32];
+        Hkdf::<Sha256>::new(Some(salt), &input)
+            .expand(info, &mut key)
+            .expect("32 bytes is a valid HKDF-SHA256 output length");
+        Self {
+            key,
+            algorithm: CipherAlgorithm::default(),
+        }
+    }
+
+    pub fn with_algorithm(mut self, algorithm: CipherAlgorithm) -> Self {
+        self.algorithm = algorithm;
+        self
     }
 
     pub fn as_bytes(&self) -> &[u8] {
         &self.key
     }
+
+    /// The key as 64 lowercase hex characters, for backing it up outside
+    /// of shares. The string is built in a buffer that is wiped afterwards,
+    /// but the returned copy is the caller's to protect.
+    pub fn to_hex(&self) -> String {
+        let mut hex = Zeroizing::new(String::with_capacity(self.key.len() * 2));
+        for byte in &self.key {
+            hex.push(char::from(HEX_DIGITS[(byte >> 4) as usize]));
+            hex.push(char::from(HEX_DIGITS[(byte & 0x0f) as usize]));
+        }
+        hex.as_str().to_owned()
+    }
+
+    /// Parses a key written by [`to_hex`](Self::to_hex). Either case is
+    /// accepted.
+    pub fn from_hex(hex: &str) -> Result<Self, CryptoError> {
+        if hex.len() != 64 {
+            return Err(CryptoError::InvalidKeyLength);
+        }
+        if !hex.bytes().all(|c| c.is_ascii_hexdigit()) {
+            return Err(CryptoError::InvalidKeyEncoding);
+        }
+        let mut key = [0u8; 32];
+        for (byte, pair) in key.iter_mut().zip(hex.as_bytes().chunks_exact(2)) {
+            let pair = std::str::from_utf8(pair).map_err(|_| CryptoError::InvalidKeyEncoding)?;
+            *byte = u8::from_str_radix(pair, 16).map_err(|_| CryptoError::InvalidKeyEncoding)?;
+        }
+        Ok(Self {
+            key,
+            algorithm: CipherAlgorithm::default(),
+        })
+    }
+
+    /// The first and last four hex characters of the key, e.g.
+    /// `1a2b****7e8f`, for telling keys apart in logs.
+    pub fn display_hex_redacted(&self) -> String {
+        let hex = Zeroizing::new(self.to_hex());
+        format!("{}****{}", &hex[..4], &hex[hex.len() - 4..])
+    }
+
+    /// Short fingerprint of the key: the first bytes of HMAC-SHA256 over a
+    /// fixed label. Storing it lets a wrong key be recognised before any
+    /// ciphertext is touched, without revealing anything useful about it.
+    pub fn check_value(&self) -> [u8; KEY_CHECK_LEN] {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&self.key).expect("HMAC accepts any key length");
+        mac.update(b"CryptIt key check v1");
+        let tag = mac.finalize().into_bytes();
+        let mut check = [0u8; KEY_CHECK_LEN];
+        check.copy_from_slice(&tag[..KEY_CHECK_LEN]);
+        check
+    }
 }
 
+#[derive(Debug, Clone, PartialEq)]
 pub struct EncryptedData {
-    pub nonce: [u8; 12], // 96-bit nonce for AES-GCM
+    pub nonce: Vec<u8>, // CipherAlgorithm::nonce_len bytes
     pub ciphertext: Vec<u8>,
 }
 
+impl Zeroize for EncryptedData {
+    fn zeroize(&mut self) {
+        self.nonce.zeroize();
+        self.ciphertext.zeroize();
+    }
+}
+
+impl Drop for EncryptedData {
+    fn drop(&mut self) {
+        self.zeroize();
+    }
+}
+
+impl ZeroizeOnDrop for EncryptedData {}
+
+/// Encrypts with the algorithm carried by `key`.
 pub fn encrypt_data(data: &[u8], key: &EncryptionKey) -> Result<EncryptedData, CryptoError> {
-    let cipher = Aes256Gcm::new_from_slice(&key.key)
-        .map_err(|_| CryptoError::EncryptionFailed)?;
-    
-    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
-    let ciphertext = cipher
-        .encrypt(&nonce, data)
-        .map_err(|_| CryptoError::EncryptionFailed)?;
+    encrypt_data_with_algo(data, key, key.algorithm)
+}
 
-    let mut nonce_array = [0u8; 12];
-    nonce_array.copy_from_slice(&nonce);
+/// Decrypts with the algorithm carried by `key`.
+pub fn decrypt_data(
+    encrypted_data: &EncryptedData,
+    key: &EncryptionKey,
+) -> Result<Zeroizing<Vec<u8>>, CryptoError> {
+    decrypt_data_with_algo(encrypted_data, key, key.algorithm)
+}
 
+/// Like [`encrypt_data_with_aad`], but with a nonce chosen by the caller,
+/// which must never be used with `key` again.
+pub fn encrypt_data_with_nonce(
+    data: &[u8],
+    key: &EncryptionKey,
+    nonce: &[u8],
+    aad: &[u8],
+) -> Result<EncryptedData, CryptoError> {
+    check_nonce_len(key.algorithm, nonce)?;
+    let ciphertext = match key.algorithm {
+        CipherAlgorithm::Aes256Gcm => seal_with_nonce::<Aes256Gcm>(data, &key.key, nonce, aad),
+        CipherAlgorithm::ChaCha20Poly1305 => seal_with_nonce::<ChaCha20Poly1305>(data, &key.key, nonce, aad),
+        CipherAlgorithm::Aes256GcmSiv => seal_with_nonce::<Aes256GcmSiv>(data, &key.key, nonce, aad),
+        CipherAlgorithm::XChaCha20Poly1305 => seal_with_nonce::<XChaCha20Poly1305>(data, &key.key, nonce, aad),
+    }?;
     Ok(EncryptedData {
-        nonce: nonce_array,
+        nonce: nonce.to_vec(),
         ciphertext,
     })
 }
 
-pub fn decrypt_data(
+/// Compresses `data` with zstd at `level`, or returns `None` when that
+/// doesn't make it smaller, as with JPEG, MP4 and other compressed formats.
+pub fn compress(data: &[u8], level: i32) -> Result<Option<Zeroizing<Vec<u8>>>, CryptoError> {
+    let compressed = zstd::bulk::compress(data, level)
+        .map(Zeroizing::new)
+        .map_err(CryptoError::CompressionFailed)?;
+    Ok((compressed.len() < data.len()).then_some(compressed))
+}
+
+/// Compresses `data` with [`compress`] before encrypting it with
+/// [`encrypt_data`]; once encrypted it won't compress any more. Data that
+/// doesn't get smaller is encrypted as it is. Also returns whether `data`
+/// was compressed, which [`decrypt_and_decompress`] has to be told.
+pub fn compress_and_encrypt(data: &[u8], key: &EncryptionKey, level: i32) -> Result<(EncryptedData, bool), CryptoError> {
+    match compress(data, level)? {
+        Some(compressed) => Ok((encrypt_data(&compressed, key)?, true)),
+        None => Ok((encrypt_data(data, key)?, false)),
+    }
+}
+
+/// Reverses [`compress_and_encrypt`], decompressing only when `compressed`.
+/// Nothing is decompressed until the ciphertext has authenticated, and
+/// never more than `max_len` bytes.
+pub fn decrypt_and_decompress(
     encrypted_data: &EncryptedData,
     key: &EncryptionKey,
-) -> Result<Vec<u8>, CryptoError> {
-    let cipher = Aes256Gcm::new_from_slice(&key.key)
-        .map_err(|_| CryptoError::DecryptionFailed)?;
+    compressed: bool,
+    max_len: usize,
+) -> Result<Zeroizing<Vec<u8>>, CryptoError> {
+    let plaintext = decrypt_data(encrypted_data, key)?;
+    if !compressed {
+        return Ok(plaintext);
+    }
+    zstd::bulk::decompress(&plaintext, max_len)
+        .map(Zeroizing::new)
+        .map_err(CryptoError::DecompressionFailed)
+}
+
+/// Like [`encrypt_data`], but binds `aad` to the ciphertext: it is not
+/// encrypted, yet decryption fails unless exactly the same bytes are supplied.
+pub fn encrypt_data_with_aad(
+    data: &[u8],
+    key: &EncryptionKey,
+    aad: &[u8],
+) -> Result<EncryptedData, CryptoError> {
+    match key.algorithm {
+        CipherAlgorithm::Aes256Gcm => seal::<Aes256Gcm>(data, &key.key, aad),
+        CipherAlgorithm::ChaCha20Poly1305 => seal::<ChaCha20Poly1305>(data, &key.key, aad),
+        CipherAlgorithm::Aes256GcmSiv => seal::<Aes256GcmSiv>(data, &key.key, aad),
+        CipherAlgorithm::XChaCha20Poly1305 => seal::<XChaCha20Poly1305>(data, &key.key, aad),
+    }
+}
+
+pub fn decrypt_data_with_aad(
+    encrypted_data: &EncryptedData,
+    key: &EncryptionKey,
+    aad: &[u8],
+) -> Result<Zeroizing<Vec<u8>>, CryptoError> {
+    match key.algorithm {
+        CipherAlgorithm::Aes256Gcm => open::<Aes256Gcm>(encrypted_data, &key.key, aad),
+        CipherAlgorithm::ChaCha20Poly1305 => open::<ChaCha20Poly1305>(encrypted_data, &key.key, aad),
+        CipherAlgorithm::Aes256GcmSiv => open::<Aes256GcmSiv>(encrypted_data, &key.key, aad),
+        CipherAlgorithm::XChaCha20Poly1305 => open::<XChaCha20Poly1305>(encrypted_data, &key.key, aad),
+    }
+}
+
+pub fn encrypt_data_with_algo(
+    data: &[u8],
+    key: &EncryptionKey,
+    algorithm: CipherAlgorithm,
+) -> Result<EncryptedData, CryptoError> {
+    match algorithm {
+        CipherAlgorithm::Aes256Gcm => seal::<Aes256Gcm>(data, &key.key, &[]),
+        CipherAlgorithm::ChaCha20Poly1305 => seal::<ChaCha20Poly1305>(data, &key.key, &[]),
+        CipherAlgorithm::Aes256GcmSiv => seal::<Aes256GcmSiv>(data, &key.key, &[]),
+        CipherAlgorithm::XChaCha20Poly1305 => seal::<XChaCha20Poly1305>(data, &key.key, &[]),
+    }
+}
+
+pub fn decrypt_data_with_algo(
+    encrypted_data: &EncryptedData,
+    key: &EncryptionKey,
+    algorithm: CipherAlgorithm,
+) -> Result<Zeroizing<Vec<u8>>, CryptoError> {
+    match algorithm {
+        CipherAlgorithm::Aes256Gcm => open::<Aes256Gcm>(encrypted_data, &key.key, &[]),
+        CipherAlgorithm::ChaCha20Poly1305 => open::<ChaCha20Poly1305>(encrypted_data, &key.key, &[]),
+        CipherAlgorithm::Aes256GcmSiv => open::<Aes256GcmSiv>(encrypted_data, &key.key, &[]),
+        CipherAlgorithm::XChaCha20Poly1305 => open::<XChaCha20Poly1305>(encrypted_data, &key.key, &[]),
+    }
+}
+
+/// Encrypts with AES-256-GCM-SIV whatever algorithm `key` carries. See
+/// [`CipherAlgorithm::Aes256GcmSiv`] for when to prefer it.
+pub fn encrypt_data_siv(data: &[u8], key: &EncryptionKey) -> Result<EncryptedData, CryptoError> {
+    encrypt_data_with_algo(data, key, CipherAlgorithm::Aes256GcmSiv)
+}
+
+pub fn decrypt_data_siv(
+    encrypted_data: &EncryptedData,
+    key: &EncryptionKey,
+) -> Result<Zeroizing<Vec<u8>>, CryptoError> {
+    decrypt_data_with_algo(encrypted_data, key, CipherAlgorithm::Aes256GcmSiv)
+}
+
+/// Encrypts `key` under `wrapping_key` with AES-256-GCM, whichever cipher
+/// either key is for, so one file key can be opened more than one way.
+/// Returns `[nonce][wrapped key][tag]`, [`WRAPPED_KEY_LEN`] bytes.
+pub fn wrap_key(key: &EncryptionKey, wrapping_key: &EncryptionKey) -> Result<Vec<u8>, CryptoError> {
+    let wrapped = seal::<Aes256Gcm>(&key.key, &wrapping_key.key, KEY_WRAP_AAD)?;
+    let mut bytes = wrapped.nonce.clone();
+    bytes.extend_from_slice(&wrapped.ciphertext);
+    Ok(bytes)
+}
+
+/// Reverses [`wrap_key`]. A wrong `wrapping_key` fails authentication. The
+/// key comes back for the default cipher; set the file's with
+/// [`EncryptionKey::with_algorithm`].
+pub fn unwrap_key(wrapped: &[u8], wrapping_key: &EncryptionKey) -> Result<EncryptionKey, CryptoError> {
+    if wrapped.len() != WRAPPED_KEY_LEN {
+        return Err(CryptoError::InvalidKeyLength);
+    }
+    let (nonce, ciphertext) = wrapped.split_at(CipherAlgorithm::Aes256Gcm.nonce_len());
+    let key = open_with_nonce::<Aes256Gcm>(ciphertext, &wrapping_key.key, nonce, KEY_WRAP_AAD)?;
+    EncryptionKey::from_bytes(&key)
+}
+
+/// Generates an X25519 keypair for receiving files, as `(private, public)`.
+pub fn generate_keypair() -> (Zeroizing<[u8; X25519_KEY_LEN]>, [u8; X25519_KEY_LEN]) {
+    let private = StaticSecret::random_from_rng(OsRng);
+    let public = PublicKey::from(&private).to_bytes();
+    (Zeroizing::new(private.to_bytes()), public)
+}
+
+/// Seals `key` so that only the holder of the private key for
+/// `recipient_public` can open it: an ephemeral X25519 exchange with the
+/// recipient, HKDF-SHA256 over the shared secret and both public keys, and
+/// [`wrap_key`] under the result. Returns [`RECIPIENT_SLOT_LEN`] bytes.
+pub fn seal_key_to_recipient(key: &EncryptionKey, recipient_public: &[u8; X25519_KEY_LEN]) -> Result<Vec<u8>, CryptoError> {
+    let recipient_public = PublicKey::from(*recipient_public);
+    let ephemeral = EphemeralSecret::random_from_rng(OsRng);
+    let ephemeral_public = PublicKey::from(&ephemeral);
+    let shared = ephemeral.diffie_hellman(&recipient_public);
+    if !shared.was_contributory() {
+        return Err(CryptoError::InvalidPublicKey);
+    }
+    let wrapping_key = recipient_wrapping_key(shared.as_bytes(), &ephemeral_public, &recipient_public);
+
+    let mut slot = ephemeral_public.to_bytes().to_vec();
+    slot.extend(wrap_key(key, &wrapping_key)?);
+    Ok(slot)
+}
+
+/// Reverses [`seal_key_to_recipient`]. A slot sealed to someone else fails
+/// with [`CryptoError::AuthenticationFailed`].
+pub fn open_recipient_slot(slot: &[u8], private_key: &[u8; X25519_KEY_LEN]) -> Result<EncryptionKey, CryptoError> {
+    if slot.len() != RECIPIENT_SLOT_LEN {
+        return Err(CryptoError::InvalidKeyLength);
+    }
+    let (ephemeral_public, wrapped) = slot.split_at(X25519_KEY_LEN);
+    let ephemeral_public = PublicKey::from(<[u8; X25519_KEY_LEN]>::try_from(ephemeral_public).expect("length checked above"));
+    let private = StaticSecret::from(*private_key);
+    let shared = private.diffie_hellman(&ephemeral_public);
+    if !shared.was_contributory() {
+        return Err(CryptoError::AuthenticationFailed);
+    }
+    let wrapping_key = recipient_wrapping_key(shared.as_bytes(), &ephemeral_public, &PublicKey::from(&private));
+    unwrap_key(wrapped, &wrapping_key)
+}
+
+fn recipient_wrapping_key(shared: &[u8], ephemeral_public: &PublicKey, recipient_public: &PublicKey) -> EncryptionKey {
+    let mut salt = ephemeral_public.to_bytes().to_vec();
+    salt.extend_from_slice(recipient_public.as_bytes());
+    let mut key = [0u8; 32];
+    Hkdf::<Sha256>::new(Some(&salt), shared)
+        .expand(RECIPIENT_INFO, &mut key)
+        .expect("32 bytes is a valid HKDF-SHA256 output length");
+    EncryptionKey {
+        key,
+        algorithm: CipherAlgorithm::Aes256Gcm,
+    }
+}
+
+/// Generates an Ed25519 keypair for signing files, as `(private, public)`.
+pub fn generate_signing_key() -> (Zeroizing<[u8; SIGNING_KEY_LEN]>, [u8; SIGNING_KEY_LEN]) {
+    let mut private = Zeroizing::new([0u8; SIGNING_KEY_LEN]);
+    OsRng.fill_bytes(private.as_mut());
+    let public = signing_public_key(&private);
+    (private, public)
+}
+
+/// Public key of the Ed25519 `private` key.
+pub fn signing_public_key(private: &[u8; SIGNING_KEY_LEN]) -> [u8; SIGNING_KEY_LEN] {
+    SigningKey::from_bytes(private).verifying_key().to_bytes()
+}
+
+/// Signs `digest`, the SHA-256 of the data being vouched for, with the
+/// Ed25519 `private` key.
+pub fn sign_digest(private: &[u8; SIGNING_KEY_LEN], digest: &[u8; 32]) -> [u8; SIGNATURE_LEN] {
+    SigningKey::from_bytes(private)
+        .sign(&[SIGNATURE_CONTEXT, digest].concat())
+        .to_bytes()
+}
+
+/// Checks a signature from [`sign_digest`] against the signer's `public`
+/// key, rejecting the malleable encodings plain Ed25519 verification lets
+/// through.
+pub fn verify_digest(
+    public: &[u8; SIGNING_KEY_LEN],
+    digest: &[u8; 32],
+    signature: &[u8; SIGNATURE_LEN],
+) -> Result<(), CryptoError> {
+    let public = VerifyingKey::from_bytes(public).map_err(|_| CryptoError::InvalidSigningKey)?;
+    public
+        .verify_strict(&[SIGNATURE_CONTEXT, digest].concat(), &Signature::from_bytes(signature))
+        .map_err(|_| CryptoError::BadSignature)
+}
+
+/// SHA-512 of everything `reader` yields, read in pieces so a large keyfile
+/// isn't held in memory. An empty keyfile is refused.
+pub fn hash_keyfile(reader: &mut impl Read) -> Result<Zeroizing<[u8; KEYFILE_DIGEST_LEN]>, CryptoError> {
+    let mut hasher = Sha512::new();
+    let mut buf = Zeroizing::new([0u8; 64 * 1024]);
+    let mut total = 0u64;
+    loop {
+        let n = reader.read(&mut buf[..])?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+        total += n as u64;
+    }
+    if total == 0 {
+        return Err(CryptoError::EmptyKeyfile);
+    }
+    Ok(Zeroizing::new(hasher.finalize().into()))
+}
+
+fn seal<C>(data: &[u8], key: &[u8], aad: &[u8]) -> Result<EncryptedData, CryptoError>
+where
+    C: KeyInit + Aead + AeadCore,
+{
+    let nonce = C::generate_nonce(&mut OsRng).to_vec();
+    let ciphertext = seal_with_nonce::<C>(data, key, &nonce, aad)?;
+    Ok(EncryptedData { nonce, ciphertext })
+}
+
+fn open<C>(encrypted_data: &EncryptedData, key: &[u8], aad: &[u8]) -> Result<Zeroizing<Vec<u8>>, CryptoError>
+where
+    C: KeyInit + Aead + AeadCore,
+{
+    open_with_nonce::<C>(&encrypted_data.ciphertext, key, &encrypted_data.nonce, aad)
+}
+
+fn seal_with_nonce<C>(data: &[u8], key: &[u8], nonce: &[u8], aad: &[u8]) -> Result<Vec<u8>, CryptoError>
+where
+    C: KeyInit + Aead + AeadCore,
+{
+    let nonce = nonce_for::<C>(nonce)?;
+    let cipher = C::new_from_slice(key)
+        .map_err(|_| CryptoError::EncryptionFailed)?;
     
-    let nonce = Nonce::from_slice(&encrypted_data.nonce);
+    cipher
+        .encrypt(nonce, Payload { msg: data, aad })
+        .map_err(|_| CryptoError::EncryptionFailed)
+}
+
+fn open_with_nonce<C>(
+    ciphertext: &[u8],
+    key: &[u8],
+    nonce: &[u8],
+    aad: &[u8],
+) -> Result<Zeroizing<Vec<u8>>, CryptoError>
+where
+    C: KeyInit + Aead + AeadCore,
+{
+    let nonce = nonce_for::<C>(nonce)?;
+    if ciphertext.len() < TAG_LEN {
+        return Err(CryptoError::TruncatedData { actual: ciphertext.len(), minimum: TAG_LEN });
+    }
+    let cipher = C::new_from_slice(key)
+        .map_err(|_| CryptoError::DecryptionFailed)?;
     
     cipher
-        .decrypt(nonce, encrypted_data.ciphertext.as_ref())
-        .map_err(|_| CryptoError::DecryptionFailed)
+        .decrypt(nonce, Payload { msg: ciphertext, aad })
+        .map(Zeroizing::new)
+        .map_err(|_| CryptoError::AuthenticationFailed)
+}
+
+// A nonce read from a file under the wrong cipher has the wrong length;
+// report it rather than let `from_slice` panic
+fn nonce_for<C: AeadCore>(nonce: &[u8]) -> Result<&Nonce<C>, CryptoError> {
+    let expected = C::NonceSize::USIZE;
+    if nonce.len() != expected {
+        return Err(CryptoError::InvalidNonceLength { expected, actual: nonce.len() });
+    }
+    Ok(Nonce::<C>::from_slice(nonce))
+}
+
+fn check_nonce_len(algorithm: CipherAlgorithm, nonce: &[u8]) -> Result<(), CryptoError> {
+    let expected = algorithm.nonce_len();
+    if nonce.len() != expected {
+        return Err(CryptoError::InvalidNonceLength { expected, actual: nonce.len() });
+    }
+    Ok(())
+}
+
+/// Encrypts `reader` in `chunk_size` pieces and writes `[nonce][chunks]` to
+/// `writer`, so inputs of any size can be encrypted in constant memory.
+pub fn encrypt_stream(
+    reader: &mut impl Read,
+    writer: &mut impl Write,
+    key: &EncryptionKey,
+    chunk_size: usize,
+) -> Result<(), CryptoError> {
+    let base_nonce = generate_nonce(key.algorithm);
+    writer.write_all(&base_nonce)?;
+    encrypt_chunks(reader, writer, key, chunk_size, &base_nonce, &[], &mut |_| {})
+}
+
+/// Reverses [`encrypt_stream`]. Output is written chunk by chunk as each one
+/// authenticates, so on error `writer` may already hold a prefix of the
+/// plaintext and should be discarded.
+pub fn decrypt_stream(
+    reader: &mut impl Read,
+    writer: &mut impl Write,
+    key: &EncryptionKey,
+    chunk_size: usize,
+) -> Result<(), CryptoError> {
+    let mut base_nonce = vec![0u8; key.algorithm.nonce_len()];
+    reader.read_exact(&mut base_nonce)?;
+    decrypt_chunks(reader, writer, key, chunk_size, &base_nonce, &[], &mut |_| {})
+}
+
+/// Encrypts `reader` as a sequence of `[len: u32 LE][ciphertext]` chunks.
+///
+/// Chunk `i` is sealed under `base_nonce` with its last four bytes replaced
+/// by `i` (big-endian), so no nonce repeats within a stream. The chunk index
+/// and a final-chunk flag are appended to `aad`, which makes reordered,
+/// dropped or truncated chunks fail authentication.
+///
+/// `progress` is called after each chunk with the plaintext bytes done so far.
+pub fn encrypt_chunks(
+    reader: &mut impl Read,
+    writer: &mut impl Write,
+    key: &EncryptionKey,
+    chunk_size: usize,
+    base_nonce: &[u8],
+    aad: &[u8],
+    progress: &mut dyn FnMut(u64),
+) -> Result<(), CryptoError> {
+    if chunk_size == 0 || chunk_size > u32::MAX as usize - TAG_LEN {
+        return Err(CryptoError::EncryptionFailed);
+    }
+    check_nonce_len(key.algorithm, base_nonce)?;
+
+    let mut chunk = vec![0u8; chunk_size];
+    let mut next = vec![0u8; chunk_size];
+    let mut len = read_full(reader, &mut chunk)?;
+    let mut index = 0u32;
+    let mut processed = 0u64;
+    loop {
+        // Read ahead so the last chunk can be flagged as such
+        let next_len = if len == chunk_size { read_full(reader, &mut next)? } else { 0 };
+        let last = next_len == 0;
+
+        let nonce = chunk_nonce(base_nonce, index);
+        let ciphertext = seal_chunk(&chunk[..len], key, &nonce, &chunk_aad(aad, index, last))?;
+        writer.write_all(&(ciphertext.len() as u32).to_le_bytes())?;
+        writer.write_all(&ciphertext)?;
+        processed += len as u64;
+        progress(processed);
+
+        if last {
+            break;
+        }
+        std::mem::swap(&mut chunk, &mut next);
+        len = next_len;
+        index = index.checked_add(1).ok_or(CryptoError::EncryptionFailed)?;
+    }
+    chunk.zeroize();
+    next.zeroize();
+    Ok(())
+}
+
+/// Encrypts `data` in `chunk_size` pieces on `num_threads` threads (0 for one
+/// per core), returning the chunks in order. They are sealed exactly as
+/// [`encrypt_chunks`] seals them: a random base nonce with the chunk index
+/// in its last four bytes, and the index and final-chunk flag as associated
+/// data. Unlike [`encrypt_chunks`], the whole input has to be in memory.
+pub fn encrypt_data_parallel(
+    data: &[u8],
+    key: &EncryptionKey,
+    chunk_size: usize,
+    num_threads: usize,
+) -> Result<Vec<EncryptedData>, CryptoError> {
+    if chunk_size == 0 || chunk_size > u32::MAX as usize - TAG_LEN {
+        return Err(CryptoError::EncryptionFailed);
+    }
+    // An empty input is still one (empty) final chunk
+    let chunk_count = data.len().div_ceil(chunk_size).max(1);
+    if chunk_count - 1 > u32::MAX as usize {
+        return Err(CryptoError::EncryptionFailed);
+    }
+    let base_nonce = generate_nonce(key.algorithm);
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(num_threads)
+        .build()
+        .map_err(|_| CryptoError::EncryptionFailed)?;
+
+    pool.install(|| {
+        let encrypt = |(index, chunk): (usize, &[u8])| {
+            let index = index as u32;
+            let nonce = chunk_nonce(&base_nonce, index);
+            let last = index as usize == chunk_count - 1;
+            let ciphertext = seal_chunk(chunk, key, &nonce, &chunk_aad(&[], index, last))?;
+            Ok(EncryptedData { nonce, ciphertext })
+        };
+        if data.is_empty() {
+            return encrypt((0, data)).map(|chunk| vec![chunk]);
+        }
+        data.par_chunks(chunk_size).enumerate().map(encrypt).collect()
+    })
+}
+
+fn seal_chunk(chunk: &[u8], key: &EncryptionKey, nonce: &[u8], aad: &[u8]) -> Result<Vec<u8>, CryptoError> {
+    match key.algorithm {
+        CipherAlgorithm::Aes256Gcm => seal_with_nonce::<Aes256Gcm>(chunk, &key.key, nonce, aad),
+        CipherAlgorithm::ChaCha20Poly1305 => seal_with_nonce::<ChaCha20Poly1305>(chunk, &key.key, nonce, aad),
+        CipherAlgorithm::Aes256GcmSiv => seal_with_nonce::<Aes256GcmSiv>(chunk, &key.key, nonce, aad),
+        CipherAlgorithm::XChaCha20Poly1305 => seal_with_nonce::<XChaCha20Poly1305>(chunk, &key.key, nonce, aad),
+    }
+}
+
+/// Reverses [`encrypt_chunks`], stopping at the first chunk that fails to
+/// authenticate.
+pub fn decrypt_chunks(
+    reader: &mut impl Read,
+    writer: &mut impl Write,
+    key: &EncryptionKey,
+    chunk_size: usize,
+    base_nonce: &[u8],
+    aad: &[u8],
+    progress: &mut dyn FnMut(u64),
+) -> Result<(), CryptoError> {
+    check_nonce_len(key.algorithm, base_nonce)?;
+    let mut next_len = read_chunk_len(reader)?;
+    let mut index = 0u32;
+    let mut processed = 0u64;
+    loop {
+        let len = next_len.ok_or(CryptoError::StreamTruncated)?;
+        if len < TAG_LEN || len > chunk_size + TAG_LEN {
+            return Err(CryptoError::ChunkCorrupted(index));
+        }
+        let mut ciphertext = vec![0u8; len];
+        reader.read_exact(&mut ciphertext).map_err(|e| match e.kind() {
+            io::ErrorKind::UnexpectedEof => CryptoError::StreamTruncated,
+            _ => CryptoError::Io(e),
+        })?;
+
+        // The chunk is the last one exactly when nothing follows it
+        next_len = read_chunk_len(reader)?;
+        let last = next_len.is_none();
+
+        let open_chunk = |last: bool| open_chunk(&ciphertext, key, base_nonce, aad, index, last);
+        let plaintext = match open_chunk(last) {
+            Ok(plaintext) => plaintext,
+            // A chunk that input ends after, but that wasn't sealed as the
+            // final one, means the chunks after it were cut off
+            Err(_) if last && open_chunk(false).is_ok() => return Err(CryptoError::StreamTruncated),
+            Err(_) => return Err(CryptoError::ChunkCorrupted(index)),
+        };
+        writer.write_all(&plaintext)?;
+        processed += plaintext.len() as u64;
+        progress(processed);
+
+        if last {
+            return Ok(());
+        }
+        index = index.checked_add(1).ok_or(CryptoError::ChunkCorrupted(index))?;
+    }
+}
+
+/// Decrypts chunk `index` of the output of [`encrypt_chunks`] on its own,
+/// for reading part of a file. `ciphertext` excludes the length prefix, and
+/// `last` says whether this is the final chunk, which is authenticated too.
+pub fn decrypt_chunk(
+    ciphertext: &[u8],
+    key: &EncryptionKey,
+    base_nonce: &[u8],
+    aad: &[u8],
+    index: u32,
+    last: bool,
+) -> Result<Zeroizing<Vec<u8>>, CryptoError> {
+    check_nonce_len(key.algorithm, base_nonce)?;
+    open_chunk(ciphertext, key, base_nonce, aad, index, last).map_err(|_| CryptoError::ChunkCorrupted(index))
+}
+
+fn open_chunk(
+    ciphertext: &[u8],
+    key: &EncryptionKey,
+    base_nonce: &[u8],
+    aad: &[u8],
+    index: u32,
+    last: bool,
+) -> Result<Zeroizing<Vec<u8>>, CryptoError> {
+    let nonce = chunk_nonce(base_nonce, index);
+    let chunk_aad = chunk_aad(aad, index, last);
+    match key.algorithm {
+        CipherAlgorithm::Aes256Gcm => open_with_nonce::<Aes256Gcm>(ciphertext, &key.key, &nonce, &chunk_aad),
+        CipherAlgorithm::ChaCha20Poly1305 => open_with_nonce::<ChaCha20Poly1305>(ciphertext, &key.key, &nonce, &chunk_aad),
+        CipherAlgorithm::Aes256GcmSiv => open_with_nonce::<Aes256GcmSiv>(ciphertext, &key.key, &nonce, &chunk_aad),
+        CipherAlgorithm::XChaCha20Poly1305 => {
+            open_with_nonce::<XChaCha20Poly1305>(ciphertext, &key.key, &nonce, &chunk_aad)
+        }
+    }
+}
+
+/// Number of chunks [`encrypt_chunks`] writes for a `ciphertext_len`-byte
+/// output. Every chunk but the last holds exactly `chunk_size` bytes.
+pub fn chunk_count(ciphertext_len: u64, chunk_size: usize) -> u64 {
+    ciphertext_len.div_ceil(chunk_size as u64 + CHUNK_OVERHEAD).max(1)
+}
+
+/// Size of the output of [`encrypt_chunks`] for `plaintext_len` input bytes.
+/// Empty input still produces one (empty) final chunk.
+pub fn chunked_ciphertext_len(plaintext_len: u64, chunk_size: usize) -> u64 {
+    let chunks = plaintext_len.div_ceil(chunk_size as u64).max(1);
+    plaintext_len + chunks * CHUNK_OVERHEAD
+}
+
+/// Inverse of [`chunked_ciphertext_len`], for well-formed lengths.
+pub fn chunked_plaintext_len(ciphertext_len: u64, chunk_size: usize) -> u64 {
+    ciphertext_len.saturating_sub(chunk_count(ciphertext_len, chunk_size) * CHUNK_OVERHEAD)
+}
+
+fn chunk_nonce(base_nonce: &[u8], index: u32) -> Vec<u8> {
+    let mut nonce = base_nonce.to_vec();
+    let counter = nonce.len() - CHUNK_COUNTER_LEN;
+    nonce[counter..].copy_from_slice(&index.to_be_bytes());
+    nonce
+}
+
+fn chunk_aad(aad: &[u8], index: u32, last: bool) -> Vec<u8> {
+    let mut chunk_aad = Vec::with_capacity(aad.len() + 5);
+    chunk_aad.extend_from_slice(aad);
+    chunk_aad.extend_from_slice(&index.to_be_bytes());
+    chunk_aad.push(last as u8);
+    chunk_aad
+}
+
+/// Reads until `buf` is full or the input ends, returning the bytes read.
+fn read_full(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+/// Reads a chunk length prefix, or `None` at a clean end of input.
+fn read_chunk_len(reader: &mut impl Read) -> Result<Option<usize>, CryptoError> {
+    let mut len = [0u8; 4];
+    match read_full(reader, &mut len)? {
+        0 => Ok(None),
+        4 => Ok(Some(u32::from_le_bytes(len) as usize)),
+        _ => Err(CryptoError::StreamTruncated),
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::mem::ManuallyDrop;
+    use std::sync::atomic::{AtomicUsize, Ordering};
 
     #[test]
     fn test_encrypt_decrypt() {
@@ -98,4 +1225,574 @@ mod tests {
         
         assert_eq!(data, decrypted.as_slice());
     }
-} 
\ No newline at end of file
+
+    #[test]
+    fn test_chacha20_round_trip() {
+        let key = EncryptionKey::generate().with_algorithm(CipherAlgorithm::ChaCha20Poly1305);
+        let data = b"Hello, world!";
+
+        let encrypted = encrypt_data(data, &key).unwrap();
+        let decrypted = decrypt_data(&encrypted, &key).unwrap();
+        assert_eq!(data, decrypted.as_slice());
+
+        // The same key bytes under the other cipher must not authenticate
+        let result = decrypt_data_with_algo(&encrypted, &key, CipherAlgorithm::Aes256Gcm);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_siv_round_trip() {
+        let key = EncryptionKey::generate();
+        let encrypted = encrypt_data_siv(b"Hello, world!", &key).unwrap();
+        assert_eq!(*decrypt_data_siv(&encrypted, &key).unwrap(), b"Hello, world!");
+        assert!(decrypt_data(&encrypted, &key).is_err());
+
+        // Under a repeated nonce SIV only reveals that the plaintexts are
+        // equal; different plaintexts share no keystream
+        let nonce = [4u8; 12];
+        let seal = |data: &[u8]| seal_with_nonce::<Aes256GcmSiv>(data, &key.key, &nonce, &[]).unwrap();
+        assert_eq!(seal(b"same message"), seal(b"same message"));
+        let (a, b) = (seal(b"message one!"), seal(b"message two!"));
+        let xor: Vec<u8> = a.iter().zip(&b).map(|(x, y)| x ^ y).collect();
+        let plaintext_xor: Vec<u8> = b"message one!".iter().zip(b"message two!").map(|(x, y)| x ^ y).collect();
+        assert_ne!(xor[..12], plaintext_xor[..]);
+    }
+
+    #[test]
+    fn test_cipher_names_and_ids() {
+        assert_eq!("aes256gcm".parse::<CipherAlgorithm>().unwrap(), CipherAlgorithm::Aes256Gcm);
+        assert_eq!("chacha20".parse::<CipherAlgorithm>().unwrap(), CipherAlgorithm::ChaCha20Poly1305);
+        assert_eq!("AES-256-GCM-SIV".parse::<CipherAlgorithm>().unwrap(), CipherAlgorithm::Aes256GcmSiv);
+        assert_eq!("XChaCha20-Poly1305".parse::<CipherAlgorithm>().unwrap(), CipherAlgorithm::XChaCha20Poly1305);
+        for algorithm in [
+            CipherAlgorithm::Aes256Gcm,
+            CipherAlgorithm::ChaCha20Poly1305,
+            CipherAlgorithm::Aes256GcmSiv,
+            CipherAlgorithm::XChaCha20Poly1305,
+        ] {
+            assert_eq!(CipherAlgorithm::from_id(algorithm.id()).unwrap(), algorithm);
+        }
+        assert_eq!("ChaCha20-Poly1305".parse::<CipherAlgorithm>().unwrap(), CipherAlgorithm::ChaCha20Poly1305);
+        assert_eq!("AES-256-GCM".parse::<CipherAlgorithm>().unwrap(), CipherAlgorithm::Aes256Gcm);
+        assert!("rot13".parse::<CipherAlgorithm>().is_err());
+        assert!(CipherAlgorithm::from_id(0).is_err());
+    }
+
+    /// Allocator for the test binary that, when a watched buffer is freed,
+    /// records whether it still held anything but zeros. Freed memory can't
+    /// be read afterwards, so the check has to happen as it's released.
+    struct WatchingAllocator;
+
+    static WATCHED: [AtomicUsize; 2] = [AtomicUsize::new(0), AtomicUsize::new(0)];
+    /// Per watched buffer: 0 while it's alive, 1 if it was freed wiped, 2 if
+    /// it was freed with its contents intact.
+    static FREED: [AtomicUsize; 2] = [AtomicUsize::new(0), AtomicUsize::new(0)];
+
+    unsafe impl GlobalAlloc for WatchingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            for (watched, freed) in WATCHED.iter().zip(&FREED) {
+                if ptr as usize == watched.load(Ordering::SeqCst) {
+                    let bytes = std::slice::from_raw_parts(ptr, layout.size());
+                    let state = if bytes.iter().all(|&b| b == 0) { 1 } else { 2 };
+                    freed.store(state, Ordering::SeqCst);
+                    watched.store(0, Ordering::SeqCst);
+                }
+            }
+            System.dealloc(ptr, layout)
+        }
+
+        unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+            System.realloc(ptr, layout, new_size)
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: WatchingAllocator = WatchingAllocator;
+
+    #[test]
+    fn test_encrypted_data_zeroized_on_drop() {
+        let mut encrypted = ManuallyDrop::new(EncryptedData {
+            nonce: vec![0xAB; 12],
+            ciphertext: vec![0xCD; 32],
+        });
+        WATCHED[0].store(encrypted.nonce.as_ptr() as usize, Ordering::SeqCst);
+        WATCHED[1].store(encrypted.ciphertext.as_ptr() as usize, Ordering::SeqCst);
+        
+        // Runs the drop glue: `Drop::drop`, then the fields, which frees both buffers
+        unsafe { ManuallyDrop::drop(&mut encrypted) };
+        
+        assert_eq!(FREED[0].load(Ordering::SeqCst), 1, "nonce freed without being wiped");
+        assert_eq!(FREED[1].load(Ordering::SeqCst), 1, "ciphertext freed without being wiped");
+    }
+
+    #[test]
+    fn test_xchacha20_round_trip() {
+        let key = EncryptionKey::generate().with_algorithm(CipherAlgorithm::XChaCha20Poly1305);
+        let encrypted = encrypt_data(b"Hello, world!", &key).unwrap();
+        assert_eq!(encrypted.nonce.len(), 24);
+        assert_eq!(*decrypt_data(&encrypted, &key).unwrap(), b"Hello, world!");
+        assert_eq!(generate_nonce(CipherAlgorithm::XChaCha20Poly1305).len(), 24);
+
+        // Opening an XChaCha20 message as AES-GCM fails on the nonce length
+        // instead of panicking inside the cipher
+        let aes = EncryptionKey::from_bytes(key.as_bytes()).unwrap();
+        assert!(matches!(
+            decrypt_data(&encrypted, &aes),
+            Err(CryptoError::InvalidNonceLength { expected: 12, actual: 24 })
+        ));
+        let short = EncryptedData {
+            nonce: vec![0u8; 12],
+            ciphertext: encrypted.ciphertext.clone(),
+        };
+        assert!(matches!(
+            decrypt_data(&short, &key),
+            Err(CryptoError::InvalidNonceLength { expected: 24, actual: 12 })
+        ));
+    }
+
+    fn unhex(hex: &str) -> Vec<u8> {
+        (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap()).collect()
+    }
+
+    #[test]
+    fn test_nist_gcm_vector() {
+        // Test Cases 15 and 16 for AES-256 from the GCM specification that
+        // NIST SP 800-38D adopts, the second with additional data
+        let key = EncryptionKey::from_bytes(&unhex("feffe9928665731c6d6a8f9467308308feffe9928665731c6d6a8f9467308308"))
+            .unwrap()
+            .with_algorithm(CipherAlgorithm::Aes256Gcm);
+        let nonce = unhex("cafebabefacedbaddecaf888");
+        let plaintext = unhex(concat!(
+            "d9313225f88406e5a55909c5aff5269a86a7a9531534f7da2e4c303d8a318a72",
+            "1c3c0c95956809532fcf0e2449a6b525b16aedf5aa0de657ba637b391aafd255",
+        ));
+        let ciphertext = unhex(concat!(
+            "522dc1f099567d07f47f37a32a84427d643a8cdcbfe5c0c97598a2bd2555d1aa",
+            "8cb08e48590dbb3da7b08b1056828838c5f61e6393ba7a0abcc9f662898015ad",
+        ));
+
+        let mut sealed = ciphertext.clone();
+        sealed.extend(unhex("b094dac5d93471bdec1a502270e3cc6c"));
+        let encrypted = EncryptedData {
+            nonce: nonce.clone(),
+            ciphertext: sealed,
+        };
+        assert_eq!(*decrypt_data(&encrypted, &key).unwrap(), plaintext);
+
+        let mut sealed = ciphertext[..60].to_vec();
+        sealed.extend(unhex("76fc6ece0f4e1768cddf8853bb2d551b"));
+        let encrypted = EncryptedData { nonce, ciphertext: sealed };
+        let aad = unhex("feedfacedeadbeeffeedfacedeadbeefabaddad2");
+        assert_eq!(*decrypt_data_with_aad(&encrypted, &key, &aad).unwrap(), plaintext[..60]);
+    }
+
+    #[test]
+    fn test_aad_must_match() {
+        let key = EncryptionKey::generate();
+        let encrypted = encrypt_data_with_aad(b"report contents", &key, b"report.pdf").unwrap();
+
+        let decrypted = decrypt_data_with_aad(&encrypted, &key, b"report.pdf").unwrap();
+        assert_eq!(*decrypted, b"report contents");
+
+        // Moving the ciphertext under another name is detected, not decrypted
+        assert!(matches!(
+            decrypt_data_with_aad(&encrypted, &key, b"invoice.pdf"),
+            Err(CryptoError::AuthenticationFailed)
+        ));
+        assert!(matches!(decrypt_data(&encrypted, &key), Err(CryptoError::AuthenticationFailed)));
+
+        // Too short to hold a tag is a different problem from a bad tag
+        let truncated = EncryptedData {
+            nonce: encrypted.nonce.clone(),
+            ciphertext: encrypted.ciphertext[..TAG_LEN - 1].to_vec(),
+        };
+        assert!(matches!(
+            decrypt_data(&truncated, &key),
+            Err(CryptoError::TruncatedData { actual: 15, minimum: TAG_LEN })
+        ));
+    }
+
+    #[test]
+    fn test_chacha20_container_round_trip() {
+        use crate::format::{parse_container, Container, FileHeader, FileMetadata};
+
+        let key = EncryptionKey::generate().with_algorithm(CipherAlgorithm::ChaCha20Poly1305);
+        let plaintext = b"some notes";
+        let ciphertext_len = (plaintext.len() + TAG_LEN) as u64;
+        let mut header = FileHeader::new(key.algorithm, [0u8; 12], ciphertext_len).with_metadata(
+            FileMetadata {
+                original_name: Some("notes.txt".to_string()),
+                ..Default::default()
+            },
+        );
+        let encrypted = encrypt_data_with_aad(plaintext, &key, &header.associated_data()).unwrap();
+        header.nonce = encrypted.nonce.clone();
+
+        let mut file = header.to_bytes();
+        file.extend_from_slice(&encrypted.ciphertext);
+
+        let Container::Current { header, ciphertext } = parse_container(&file).unwrap() else {
+            panic!("expected a current-format container");
+        };
+        assert_eq!(header.cipher, CipherAlgorithm::ChaCha20Poly1305);
+
+        // Decryption picks the cipher from the header, not from the caller
+        let key = EncryptionKey::from_bytes(key.as_bytes()).unwrap().with_algorithm(header.cipher);
+        let encrypted = EncryptedData {
+            nonce: header.nonce.clone(),
+            ciphertext: ciphertext.to_vec(),
+        };
+        let decrypted = decrypt_data_with_aad(&encrypted, &key, &header.associated_data()).unwrap();
+        assert_eq!(*decrypted, b"some notes");
+    }
+
+    #[test]
+    fn test_from_password() {
+        // Small parameters keep the test fast; the defaults are far costlier
+        let params = Argon2Params {
+            m_cost: 64,
+            t_cost: 1,
+            p_cost: 1,
+        };
+        let salt = [3u8; SALT_LEN];
+
+        let key = EncryptionKey::from_password(b"correct horse", &salt, params).unwrap();
+        let again = EncryptionKey::from_password(b"correct horse", &salt, params).unwrap();
+        assert_eq!(key.as_bytes(), again.as_bytes());
+
+        let other_salt = EncryptionKey::from_password(b"correct horse", &[4u8; SALT_LEN], params).unwrap();
+        assert_ne!(key.as_bytes(), other_salt.as_bytes());
+
+        let weak = Argon2Params { m_cost: 1, ..params };
+        assert!(EncryptionKey::from_password(b"correct horse", &salt, weak).is_err());
+    }
+
+    #[test]
+    fn test_from_password_pbkdf2() {
+        let salt = [3u8; SALT_LEN];
+        // Computed independently with Python's hashlib.pbkdf2_hmac
+        let key = EncryptionKey::from_password_pbkdf2(b"correct horse", &salt, MIN_PBKDF2_ITERATIONS).unwrap();
+        assert_eq!(key.to_hex(), "f8048a878113cbf72ad710969364bcf788a5c53d34256cdae8851edf7a5812c8");
+
+        assert!(matches!(
+            EncryptionKey::from_password_pbkdf2(b"correct horse", &salt, MIN_PBKDF2_ITERATIONS - 1),
+            Err(CryptoError::WeakKdfParameters(_))
+        ));
+        assert_eq!("PBKDF2".parse::<PasswordKdf>().unwrap(), PasswordKdf::Pbkdf2);
+        assert!("bcrypt".parse::<PasswordKdf>().is_err());
+    }
+
+    #[test]
+    fn test_from_password_scrypt() {
+        let salt = [3u8; SALT_LEN];
+        // Computed independently with Python's hashlib.scrypt
+        let params = ScryptParams { n: 1024, r: 8, p: 1 };
+        let key = EncryptionKey::from_password_scrypt(b"correct horse", &salt, params).unwrap();
+        assert_eq!(key.to_hex(), "2d3711c4bb9a33555afe2144efa9330cf93990acddbc4aeef100f10b46b66575");
+
+        for params in [
+            ScryptParams { n: 1000, r: 8, p: 1 },
+            ScryptParams { n: 1, r: 8, p: 1 },
+            ScryptParams { n: 1024, r: 0, p: 1 },
+            ScryptParams { n: 1 << 20, r: 8, p: 1 << 9 },
+        ] {
+            assert!(matches!(
+                EncryptionKey::from_password_scrypt(b"correct horse", &salt, params),
+                Err(CryptoError::WeakKdfParameters(_))
+            ));
+        }
+        assert!(ScryptParams::interactive().check().is_ok());
+        assert!(ScryptParams::sensitive().check().is_ok());
+    }
+
+    #[test]
+    fn test_hex_round_trip() {
+        let key = EncryptionKey::from_bytes(&[0xAB; 32]).unwrap();
+        let hex = key.to_hex();
+        assert_eq!(hex, "ab".repeat(32));
+        assert_eq!(EncryptionKey::from_hex(&hex).unwrap().as_bytes(), key.as_bytes());
+        assert_eq!(EncryptionKey::from_hex(&hex.to_uppercase()).unwrap().as_bytes(), key.as_bytes());
+        assert_eq!(key.display_hex_redacted(), "abab****abab");
+
+        assert!(matches!(EncryptionKey::from_hex(&hex[2..]), Err(CryptoError::InvalidKeyLength)));
+        let bad = format!("zz{}", &hex[2..]);
+        assert!(matches!(EncryptionKey::from_hex(&bad), Err(CryptoError::InvalidKeyEncoding)));
+        // Multi-byte characters can make up the length without being hex
+        let bad = format!("é{}", &hex[2..]);
+        assert!(matches!(EncryptionKey::from_hex(&bad), Err(CryptoError::InvalidKeyEncoding)));
+    }
+
+    #[test]
+    fn test_check_value() {
+        let key = EncryptionKey::generate();
+        let same = EncryptionKey::from_bytes(key.as_bytes()).unwrap();
+        assert_eq!(key.check_value(), same.check_value());
+        assert_ne!(key.check_value(), EncryptionKey::generate().check_value());
+    }
+
+    #[test]
+    fn test_stream_round_trip() {
+        // Sizes around the chunk boundary, including empty input
+        let algorithms = [CipherAlgorithm::Aes256Gcm, CipherAlgorithm::Aes256GcmSiv, CipherAlgorithm::XChaCha20Poly1305];
+        for (algorithm, len) in algorithms
+            .into_iter()
+            .flat_map(|algorithm| [0usize, 1, 63, 64, 65, 200].map(|len| (algorithm, len)))
+        {
+            let key = EncryptionKey::generate().with_algorithm(algorithm);
+            let data: Vec<u8> = (0..len).map(|i| i as u8).collect();
+            let mut encrypted = Vec::new();
+            encrypt_stream(&mut data.as_slice(), &mut encrypted, &key, 64).unwrap();
+            let nonce_len = algorithm.nonce_len() as u64;
+            assert_eq!(encrypted.len() as u64, nonce_len + chunked_ciphertext_len(len as u64, 64));
+            assert_eq!(chunked_plaintext_len(encrypted.len() as u64 - nonce_len, 64), len as u64);
+
+            let mut decrypted = Vec::new();
+            decrypt_stream(&mut encrypted.as_slice(), &mut decrypted, &key, 64).unwrap();
+            assert_eq!(decrypted, data, "round trip of {} bytes with {}", len, algorithm.name());
+        }
+    }
+
+    #[test]
+    fn test_parallel_chunks_match_stream() {
+        for (algorithm, len) in [CipherAlgorithm::Aes256Gcm, CipherAlgorithm::XChaCha20Poly1305]
+            .into_iter()
+            .flat_map(|algorithm| [0usize, 64, 65, 1000].map(|len| (algorithm, len)))
+        {
+            let key = EncryptionKey::generate().with_algorithm(algorithm);
+            let data: Vec<u8> = (0..len).map(|i| i as u8).collect();
+            let chunks = encrypt_data_parallel(&data, &key, 64, 4).unwrap();
+            assert_eq!(chunks.len(), len.div_ceil(64).max(1));
+
+            // One random prefix, then the chunk index
+            let counter = algorithm.nonce_len() - CHUNK_COUNTER_LEN;
+            for (index, chunk) in chunks.iter().enumerate() {
+                assert_eq!(chunk.nonce[..counter], chunks[0].nonce[..counter]);
+                assert_eq!(chunk.nonce[counter..], (index as u32).to_be_bytes());
+            }
+
+            // Laid out as a stream, the chunks decrypt like one
+            let mut encrypted = chunks[0].nonce.clone();
+            for chunk in &chunks {
+                encrypted.extend_from_slice(&(chunk.ciphertext.len() as u32).to_le_bytes());
+                encrypted.extend_from_slice(&chunk.ciphertext);
+            }
+            let mut decrypted = Vec::new();
+            decrypt_stream(&mut encrypted.as_slice(), &mut decrypted, &key, 64).unwrap();
+            assert_eq!(decrypted, data, "{} bytes with {}", len, algorithm.name());
+        }
+        assert!(encrypt_data_parallel(b"data", &EncryptionKey::generate(), 0, 4).is_err());
+    }
+
+    #[test]
+    fn test_wrap_key() {
+        let key = EncryptionKey::generate().with_algorithm(CipherAlgorithm::ChaCha20Poly1305);
+        let wrapping_key = EncryptionKey::generate();
+        let wrapped = wrap_key(&key, &wrapping_key).unwrap();
+        assert_eq!(wrapped.len(), WRAPPED_KEY_LEN);
+        assert_eq!(unwrap_key(&wrapped, &wrapping_key).unwrap().as_bytes(), key.as_bytes());
+
+        assert!(matches!(
+            unwrap_key(&wrapped, &EncryptionKey::generate()),
+            Err(CryptoError::AuthenticationFailed)
+        ));
+        assert!(matches!(
+            unwrap_key(&wrapped[1..], &wrapping_key),
+            Err(CryptoError::InvalidKeyLength)
+        ));
+    }
+
+    #[test]
+    fn test_from_keyfile() {
+        let salt = [3u8; SALT_LEN];
+        let digest = hash_keyfile(&mut &b"keyfile contents"[..]).unwrap();
+        // Computed independently with Python's hashlib and hmac
+        let key = EncryptionKey::from_keyfile(&digest, None, &salt);
+        assert_eq!(key.to_hex(), "f3344f6b895b5b29dbf6c91173c4356ad90b248a7ec4c87e9dda3842d4a33903");
+        let with_shares = EncryptionKey::from_keyfile(&digest, Some(&[9u8; 32]), &salt);
+        assert_eq!(with_shares.to_hex(), "db20a0a5add505ee3bd3e48a1e3c6ecd78ccb197f469fc64de7c7be427c758a6");
+
+        assert!(matches!(hash_keyfile(&mut io::empty()), Err(CryptoError::EmptyKeyfile)));
+    }
+
+    #[test]
+    fn test_recipient_slots() {
+        let key = EncryptionKey::generate();
+        let (private, public) = generate_keypair();
+        let slot = seal_key_to_recipient(&key, &public).unwrap();
+        assert_eq!(slot.len(), RECIPIENT_SLOT_LEN);
+        assert_eq!(open_recipient_slot(&slot, &private).unwrap().as_bytes(), key.as_bytes());
+
+        // Each seal uses a fresh ephemeral key
+        assert_ne!(seal_key_to_recipient(&key, &public).unwrap(), slot);
+        let (other_private, _) = generate_keypair();
+        assert!(matches!(
+            open_recipient_slot(&slot, &other_private),
+            Err(CryptoError::AuthenticationFailed)
+        ));
+        assert!(matches!(
+            seal_key_to_recipient(&key, &[0u8; X25519_KEY_LEN]),
+            Err(CryptoError::InvalidPublicKey)
+        ));
+    }
+
+    #[test]
+    fn test_signatures() {
+        let (private, public) = generate_signing_key();
+        assert_eq!(signing_public_key(&private), public);
+        let digest: [u8; 32] = Sha256::digest(b"header and ciphertext").into();
+        let signature = sign_digest(&private, &digest);
+        verify_digest(&public, &digest, &signature).unwrap();
+
+        let other: [u8; 32] = Sha256::digest(b"something else").into();
+        assert!(matches!(verify_digest(&public, &other, &signature), Err(CryptoError::BadSignature)));
+        let (_, other_public) = generate_signing_key();
+        assert!(matches!(verify_digest(&other_public, &digest, &signature), Err(CryptoError::BadSignature)));
+        let mut flipped = signature;
+        flipped[10] ^= 1;
+        assert!(matches!(verify_digest(&public, &digest, &flipped), Err(CryptoError::BadSignature)));
+    }
+
+    #[test]
+    fn test_nonce_tracker_never_repeats() {
+        use rand::{rngs::StdRng, SeedableRng};
+
+        // Two generators in the same state would hand out the same nonce
+        let mut nonces = NonceTracker::default();
+        for algorithm in [CipherAlgorithm::Aes256Gcm, CipherAlgorithm::XChaCha20Poly1305] {
+            let first = nonces.generate(algorithm, &mut StdRng::seed_from_u64(7));
+            let second = nonces.generate(algorithm, &mut StdRng::seed_from_u64(7));
+            assert_eq!(first.len(), algorithm.nonce_len());
+            assert_ne!(
+                first[..first.len() - CHUNK_COUNTER_LEN],
+                second[..second.len() - CHUNK_COUNTER_LEN],
+                "{} nonces share their chunk prefix",
+                algorithm.name()
+            );
+        }
+    }
+
+    #[test]
+    fn test_stream_cross_cipher_fails_cleanly() {
+        let data = vec![3u8; 200];
+        let all = [
+            CipherAlgorithm::Aes256Gcm,
+            CipherAlgorithm::ChaCha20Poly1305,
+            CipherAlgorithm::Aes256GcmSiv,
+            CipherAlgorithm::XChaCha20Poly1305,
+        ];
+        for written in all {
+            let key = EncryptionKey::generate().with_algorithm(written);
+            let mut encrypted = Vec::new();
+            encrypt_stream(&mut data.as_slice(), &mut encrypted, &key, 64).unwrap();
+            for read in all.into_iter().filter(|&read| read != written) {
+                // The same key bytes, opened as another cipher, which also
+                // reads a nonce of the wrong length
+                let other = EncryptionKey::from_bytes(key.as_bytes()).unwrap().with_algorithm(read);
+                let result = decrypt_stream(&mut encrypted.as_slice(), &mut Vec::new(), &other, 64);
+                assert!(result.is_err(), "{} stream opened as {}", written.name(), read.name());
+            }
+        }
+
+        // A single chunk given a base nonce of the wrong size
+        let key = EncryptionKey::generate().with_algorithm(CipherAlgorithm::XChaCha20Poly1305);
+        assert!(matches!(
+            decrypt_chunk(&[0u8; 32], &key, &[0u8; 12], b"", 0, true),
+            Err(CryptoError::InvalidNonceLength { expected: 24, actual: 12 })
+        ));
+    }
+
+    #[test]
+    fn test_stream_rejects_tampering() {
+        let key = EncryptionKey::generate();
+        let data = vec![7u8; 200];
+        let mut encrypted = Vec::new();
+        encrypt_stream(&mut data.as_slice(), &mut encrypted, &key, 64).unwrap();
+        let chunk_len = 4 + 64 + TAG_LEN;
+
+        // Flipped bit in the second chunk: the first chunk is still written
+        let mut corrupted = encrypted.clone();
+        corrupted[12 + chunk_len + 10] ^= 1;
+        let mut decrypted = Vec::new();
+        let err = decrypt_stream(&mut corrupted.as_slice(), &mut decrypted, &key, 64).unwrap_err();
+        assert!(matches!(err, CryptoError::ChunkCorrupted(1)), "unexpected error: {}", err);
+        assert_eq!(decrypted, &data[..64]);
+
+        // Dropping the final chunk, or cutting one short
+        for cut in [12 + 3 * chunk_len, 12 + 3 * chunk_len + 5, 12 + 3 * chunk_len - 1] {
+            let truncated = &encrypted[..cut];
+            let err = decrypt_stream(&mut &truncated[..], &mut Vec::new(), &key, 64).unwrap_err();
+            assert!(matches!(err, CryptoError::StreamTruncated), "cut at {}: {}", cut, err);
+        }
+
+        // Swapping two chunks
+        let mut swapped = encrypted.clone();
+        let (first, second) = (12..12 + chunk_len, 12 + chunk_len..12 + 2 * chunk_len);
+        let first_chunk = encrypted[first.clone()].to_vec();
+        swapped[first].copy_from_slice(&encrypted[second.clone()]);
+        swapped[second].copy_from_slice(&first_chunk);
+        let err = decrypt_stream(&mut swapped.as_slice(), &mut Vec::new(), &key, 64).unwrap_err();
+        assert!(matches!(err, CryptoError::ChunkCorrupted(0)), "unexpected error: {}", err);
+
+        // A chunk repeated in place of the next one, or repeated in addition
+        let mut duplicated = encrypted.clone();
+        duplicated.copy_within(12..12 + chunk_len, 12 + chunk_len);
+        let err = decrypt_stream(&mut duplicated.as_slice(), &mut Vec::new(), &key, 64).unwrap_err();
+        assert!(matches!(err, CryptoError::ChunkCorrupted(1)), "unexpected error: {}", err);
+        let mut inserted = encrypted[..12 + chunk_len].to_vec();
+        inserted.extend_from_slice(&encrypted[12..]);
+        let err = decrypt_stream(&mut inserted.as_slice(), &mut Vec::new(), &key, 64).unwrap_err();
+        assert!(matches!(err, CryptoError::ChunkCorrupted(1)), "unexpected error: {}", err);
+    }
+
+    #[test]
+    fn test_stream_multi_megabyte() {
+        let key = EncryptionKey::generate().with_algorithm(CipherAlgorithm::ChaCha20Poly1305);
+        let data: Vec<u8> = (0..3 * DEFAULT_CHUNK_SIZE + 12345).map(|i| (i % 251) as u8).collect();
+
+        let mut encrypted = Vec::new();
+        encrypt_stream(&mut data.as_slice(), &mut encrypted, &key, DEFAULT_CHUNK_SIZE).unwrap();
+        assert_eq!(
+            encrypted.len() as u64,
+            12 + chunked_ciphertext_len(data.len() as u64, DEFAULT_CHUNK_SIZE)
+        );
+
+        let mut decrypted = Vec::new();
+        decrypt_stream(&mut encrypted.as_slice(), &mut decrypted, &key, DEFAULT_CHUNK_SIZE).unwrap();
+        assert!(decrypted == data);
+    }
+
+    #[test]
+    fn test_compress_and_encrypt() {
+        let key = EncryptionKey::generate();
+        let text = b"name,value\n".repeat(1000);
+        let (encrypted, compressed) = compress_and_encrypt(&text, &key, 3).unwrap();
+        assert!(compressed);
+        assert!(encrypted.ciphertext.len() < text.len() / 10);
+        assert_eq!(&decrypt_and_decompress(&encrypted, &key, true, text.len()).unwrap()[..], &text[..]);
+
+        // The output is bounded, and the wrong key never gets as far as zstd
+        assert!(matches!(
+            decrypt_and_decompress(&encrypted, &key, true, text.len() - 1),
+            Err(CryptoError::DecompressionFailed(_))
+        ));
+        let other = EncryptionKey::generate();
+        assert!(matches!(
+            decrypt_and_decompress(&encrypted, &other, true, text.len()),
+            Err(CryptoError::AuthenticationFailed)
+        ));
+
+        // Random bytes don't get any smaller, so they're encrypted as they are
+        let mut noise = vec![0u8; 4096];
+        OsRng.fill_bytes(&mut noise);
+        assert!(compress(&noise, 3).unwrap().is_none());
+        assert!(compress(&text, 3).unwrap().is_some());
+        let (encrypted, compressed) = compress_and_encrypt(&noise, &key, 3).unwrap();
+        assert!(!compressed);
+        assert_eq!(encrypted.ciphertext.len(), noise.len() + TAG_LEN);
+        assert_eq!(&decrypt_and_decompress(&encrypted, &key, false, noise.len()).unwrap()[..], &noise[..]);
+    }
+}
@@ -0,0 +1,102 @@
+//! Error type returned by the Tauri commands.
+
+use crate::crypto::CryptoError;
+use crate::format::FormatError;
+use crate::sss::SSSError;
+use serde::Serialize;
+use std::path::Path;
+use thiserror::Error;
+
+/// Serializes as `{ "kind": "Sss", "message": "..." }`, so the frontend can
+/// branch on `kind` and still show `message` to the user.
+#[derive(Debug, Clone, PartialEq, Eq, Error, Serialize)]
+#[serde(tag = "kind", content = "message")]
+pub enum AppError {
+    /// Reading or writing files on disk failed
+    #[error("{0}")]
+    Io(String),
+
+    /// Encryption, decryption or key derivation failed, including a wrong
+    /// password or a file that fails authentication
+    #[error("{0}")]
+    Crypto(String),
+
+    /// The shares are malformed, too few, or belong to another file
+    #[error("{0}")]
+    Sss(String),
+
+    /// The input is not a readable `.cryptit` file
+    #[error("{0}")]
+    InvalidFormat(String),
+
+    /// A finished output couldn't be renamed into place because it was
+    /// written on another filesystem; writing it directly may still work
+    #[error("{0}")]
+    CrossDevice(String),
+
+    /// The output file exists and overwriting wasn't asked for
+    #[error("{0}")]
+    OutputExists(String),
+}
+
+impl AppError {
+    /// Prefixes the message with `path`, keeping the kind. Used when one of
+    /// many files in a directory operation fails.
+    pub(crate) fn for_file(self, path: &Path) -> Self {
+        let prefix = |message: String| format!("{}: {}", path.display(), message);
+        match self {
+            AppError::Io(message) => AppError::Io(prefix(message)),
+            AppError::Crypto(message) => AppError::Crypto(prefix(message)),
+            AppError::Sss(message) => AppError::Sss(prefix(message)),
+            AppError::InvalidFormat(message) => AppError::InvalidFormat(prefix(message)),
+            AppError::CrossDevice(message) => AppError::CrossDevice(prefix(message)),
+            AppError::OutputExists(message) => AppError::OutputExists(prefix(message)),
+        }
+    }
+}
+
+impl From<CryptoError> for AppError {
+    fn from(err: CryptoError) -> Self {
+        match err {
+            CryptoError::Io(_) => AppError::Io(err.to_string()),
+            _ => AppError::Crypto(err.to_string()),
+        }
+    }
+}
+
+impl From<SSSError> for AppError {
+    fn from(err: SSSError) -> Self {
+        match err {
+            SSSError::Io(_) => AppError::Io(err.to_string()),
+            _ => AppError::Sss(err.to_string()),
+        }
+    }
+}
+
+impl From<FormatError> for AppError {
+    fn from(err: FormatError) -> Self {
+        AppError::InvalidFormat(err.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serialized_shape() {
+        let err = AppError::Sss("Not enough shares".to_string());
+        assert_eq!(
+            serde_json::to_string(&err).unwrap(),
+            r#"{"kind":"Sss","message":"Not enough shares"}"#
+        );
+        assert_eq!(err.to_string(), "Not enough shares");
+    }
+
+    #[test]
+    fn test_conversions() {
+        assert!(matches!(AppError::from(CryptoError::AuthenticationFailed), AppError::Crypto(_)));
+        assert!(matches!(AppError::from(FormatError::NotCryptItFile), AppError::InvalidFormat(_)));
+        assert!(matches!(AppError::from(SSSError::InvalidThreshold), AppError::Sss(_)));
+    }
+}
@@ -0,0 +1,1958 @@
+//! On-disk layout of `.cryptit` files.
+//!
+//! ```text
+//! [magic "CRYP"][version: u8][header_len: u32 LE][header fields][ciphertext]
+//! ```
+//!
+//! Header fields are encoded as `[tag: u8][len: u16 LE][value]` records so new
+//! metadata can be added later without breaking older readers. The last field
+//! is a CRC32 of everything before it, and a copy of the whole header follows
+//! the ciphertext (see [`Trailer`]) so a damaged header can be recovered.
+//! A signed file has its signature (see [`FileHeader::signer`]) between the
+//! ciphertext and that copy.
+//! Files written before the header existed are a bare `[nonce][ciphertext]`
+//! and are still accepted by [`parse_container`]. Any of these can also be
+//! wrapped in a text [`armor`] for pasting where binary gets mangled.
+
+use base64::{engine::general_purpose, Engine};
+use crate::crypto::{self, Argon2Params, CipherAlgorithm, EncryptedData, ScryptParams, KEY_CHECK_LEN, SALT_LEN, TAG_LEN};
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+use uuid::Uuid;
+
+pub const MAGIC: &[u8; 4] = b"CRYP";
+/// Version 2 headers must end with a checksum; version 1 headers may.
+pub const FORMAT_VERSION: u8 = 2;
+
+/// Nonce length of the headerless files written by earlier releases.
+pub const LEGACY_NONCE_LEN: usize = 12;
+
+/// Smallest valid headerless file: a nonce and the tag of an empty plaintext.
+pub const MIN_LEGACY_LEN: usize = LEGACY_NONCE_LEN + TAG_LEN;
+
+/// Longest original file name that is recorded, in bytes. This matches the
+/// component limit of common filesystems.
+pub const MAX_ORIGINAL_NAME_LEN: usize = 255;
+
+/// Longest MIME type or producer version accepted from a header, in bytes.
+pub const MAX_LABEL_LEN: usize = 255;
+
+/// Longest context string a file can be bound to, in bytes.
+pub const MAX_CONTEXT_LEN: usize = 1024;
+
+/// Longest comment a header can carry, in bytes.
+pub const MAX_COMMENT_LEN: usize = 4096;
+
+/// Largest chunk size accepted from a header, so a corrupt file can't make
+/// decryption allocate an arbitrary amount of memory per chunk.
+pub const MAX_CHUNK_SIZE: u32 = 64 * 1024 * 1024;
+
+/// Upper bound on the encoded header, used when reading it from the start of
+/// a file without loading the ciphertext.
+pub const MAX_HEADER_LEN: usize = 64 * 1024;
+
+/// Version of CryptIt recorded in the files it writes.
+pub const PRODUCER_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Magic, version byte and header length.
+pub const PREFIX_LEN: usize = MAGIC.len() + 1 + 4;
+
+const TAG_NONCE: u8 = 0x01;
+const TAG_CIPHERTEXT_LEN: u8 = 0x02;
+const TAG_CIPHER: u8 = 0x03;
+const TAG_ORIGINAL_NAME: u8 = 0x04;
+const TAG_KEY_DERIVATION: u8 = 0x05;
+const TAG_SHARE_THRESHOLD: u8 = 0x06;
+const TAG_CHUNK_SIZE: u8 = 0x07;
+const TAG_PLAINTEXT_SHA256: u8 = 0x08;
+const TAG_KEY_CHECK: u8 = 0x09;
+const TAG_PLAINTEXT_LEN: u8 = 0x0A;
+const TAG_CREATED_AT: u8 = 0x0B;
+const TAG_COMPRESSION: u8 = 0x0C;
+const TAG_HEADER_CRC: u8 = 0x0D;
+const TAG_ORIGINAL_EXTENSION: u8 = 0x0E;
+const TAG_ARCHIVE: u8 = 0x0F;
+const TAG_CONTEXT: u8 = 0x10;
+const TAG_COMMENT: u8 = 0x11;
+const TAG_MODIFIED_AT: u8 = 0x12;
+const TAG_UNIX_MODE: u8 = 0x13;
+const TAG_CONTENT_TYPE: u8 = 0x14;
+const TAG_PRODUCER: u8 = 0x15;
+const TAG_FILE_ID: u8 = 0x16;
+const TAG_WRAPPED_KEY: u8 = 0x17;
+const TAG_KEYFILE: u8 = 0x18;
+const TAG_RECIPIENT: u8 = 0x19;
+const TAG_SIGNER: u8 = 0x1A;
+
+/// Encoded size of the header checksum field: tag, length and CRC32.
+const CHECKSUM_FIELD_LEN: usize = 3 + 4;
+
+/// Marks the end of a file that carries a backup copy of its header.
+pub const TRAILER_MAGIC: &[u8; 4] = b"CRYT";
+
+/// Header length and magic following the backup header copy.
+pub const TRAILER_SUFFIX_LEN: usize = 4 + TRAILER_MAGIC.len();
+
+/// First and last lines of an armored file.
+pub const ARMOR_BEGIN: &str = "-----BEGIN CRYPTIT FILE-----";
+pub const ARMOR_END: &str = "-----END CRYPTIT FILE-----";
+
+/// Base64 characters per line of an armored file.
+pub const ARMOR_LINE_LEN: usize = 64;
+
+const KDF_ARGON2ID: u8 = 1;
+const KDF_PBKDF2_SHA256: u8 = 2;
+const KDF_SCRYPT: u8 = 3;
+
+const KEYFILE_ALONE: u8 = 1;
+const KEYFILE_AND_SHARES: u8 = 2;
+const PIV_ALONE: u8 = 3;
+const PIV_AND_SHARES: u8 = 4;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum FormatError {
+    #[error("Not a CryptIt file")]
+    NotCryptItFile,
+    #[error("Unsupported format version {0}")]
+    UnsupportedVersion(u8),
+    #[error(
+        "File was created by a newer version of CryptIt (format version {version}{}); update CryptIt to open it",
+        written_by(.producer)
+    )]
+    NewerVersion { version: u8, producer: Option<String> },
+    /// A field we don't understand in a file from another release.
+    #[error("{source}{}", written_by(&Some(.producer.clone())))]
+    UnknownStructure { source: Box<FormatError>, producer: String },
+    #[error("File header is truncated")]
+    TruncatedHeader,
+    /// The header fails its checksum. `offset` is where it stops parsing, or
+    /// where the checksum is (or should be) when every field is well formed.
+    #[error("Header corrupted at offset {offset}")]
+    HeaderCorrupted { offset: usize },
+    #[error("File is truncated: {actual} bytes, expected at least {minimum}")]
+    Truncated { actual: u64, minimum: u64 },
+    #[error("Missing header field: {0}")]
+    MissingField(&'static str),
+    #[error("Invalid header field: {0}")]
+    InvalidField(&'static str),
+    /// A tag this build doesn't know. It's refused rather than skipped: the
+    /// associated data is rebuilt from the fields that were understood, so
+    /// the file couldn't be decrypted or its signature checked anyway.
+    #[error("Unknown header field {0:#04x}")]
+    UnknownField(u8),
+    #[error("File truncated: expected {expected} bytes of ciphertext, found {actual}")]
+    TruncatedCiphertext { expected: u64, actual: u64 },
+    #[error("Ciphertext length mismatch: header records {expected} bytes but found {actual}")]
+    CiphertextLengthMismatch { expected: u64, actual: u64 },
+    #[error("Armored file is corrupted: {0}")]
+    InvalidArmor(String),
+    #[error("Comment is {len} bytes long; the limit is {max}")]
+    CommentTooLong { len: usize, max: usize },
+    /// A length in the header is beyond what this field can sensibly hold.
+    #[error("Header field {field} is {len} bytes long; the limit is {max}")]
+    FieldTooLarge { field: &'static str, len: usize, max: usize },
+}
+
+/// Details about the plaintext file. These are stored in the clear so they
+/// can be shown without the key, but are passed to the cipher as associated
+/// data so any modification makes decryption fail.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct FileMetadata {
+    /// File name (with extension) of the file that was encrypted. Absent in
+    /// files written before it was recorded.
+    pub original_name: Option<String>,
+    /// Extension of the file that was encrypted, without the dot. Recorded
+    /// even when the full name is not, so the output keeps its type.
+    pub original_extension: Option<String>,
+    /// SHA-256 of the plaintext, checked again after decryption.
+    pub plaintext_sha256: Option<[u8; 32]>,
+    /// Size of the plaintext in bytes, checked against both the ciphertext
+    /// length and the decrypted output.
+    pub plaintext_len: Option<u64>,
+    /// Modification time of the file that was encrypted, since the Unix
+    /// epoch, restored on the decrypted output.
+    pub modified_at: Option<Duration>,
+    /// Permission bits of the file that was encrypted. Only recorded on Unix.
+    pub unix_mode: Option<u32>,
+    /// MIME type sniffed from the start of the plaintext (see
+    /// [`crate::mime`]), used to pick an extension when there is no name.
+    pub content_type: Option<String>,
+}
+
+impl FileMetadata {
+    /// Metadata for the file at `path`. The name is only recorded when it is
+    /// valid UTF-8 and short enough to be restored on any filesystem.
+    pub fn for_path(path: &Path) -> Self {
+        let original_name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .filter(|name| is_safe_file_name(name))
+            .map(str::to_string);
+        let original_extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .filter(|extension| is_safe_file_name(extension))
+            .map(str::to_string);
+        Self {
+            original_name,
+            original_extension,
+            ..Default::default()
+        }
+    }
+
+    /// Records the modification time and, on Unix, the permission bits from
+    /// `attributes`. Setuid, setgid and sticky bits are left out, since
+    /// they would be restored from a file anyone could have written.
+    pub fn with_attributes(mut self, attributes: &fs::Metadata) -> Self {
+        self.modified_at = attributes
+            .modified()
+            .ok()
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            self.unix_mode = Some(attributes.permissions().mode() & 0o777);
+        }
+        self
+    }
+
+    /// The recorded modification time.
+    pub fn modified_time(&self) -> Option<SystemTime> {
+        self.modified_at.and_then(|since_epoch| UNIX_EPOCH.checked_add(since_epoch))
+    }
+
+    /// The recorded name, if it is safe to use as a single file name inside
+    /// an output directory. Names containing path separators or dot
+    /// components are ignored rather than trusted.
+    pub fn safe_original_name(&self) -> Option<&str> {
+        self.original_name
+            .as_deref()
+            .filter(|name| is_safe_file_name(name))
+    }
+
+    /// The recorded extension, under the same rules as
+    /// [`safe_original_name`](Self::safe_original_name).
+    pub fn safe_original_extension(&self) -> Option<&str> {
+        self.original_extension
+            .as_deref()
+            .filter(|extension| is_safe_file_name(extension) && !extension.contains('.'))
+    }
+
+    fn write_fields(&self, out: &mut Vec<u8>) {
+        if let Some(name) = &self.original_name {
+            write_field(out, TAG_ORIGINAL_NAME, name.as_bytes());
+        }
+        if let Some(extension) = &self.original_extension {
+            write_field(out, TAG_ORIGINAL_EXTENSION, extension.as_bytes());
+        }
+        if let Some(digest) = &self.plaintext_sha256 {
+            write_field(out, TAG_PLAINTEXT_SHA256, digest);
+        }
+        if let Some(len) = self.plaintext_len {
+            write_field(out, TAG_PLAINTEXT_LEN, &len.to_le_bytes());
+        }
+        if let Some(modified_at) = self.modified_at {
+            let mut value = modified_at.as_secs().to_le_bytes().to_vec();
+            value.extend_from_slice(&modified_at.subsec_nanos().to_le_bytes());
+            write_field(out, TAG_MODIFIED_AT, &value);
+        }
+        if let Some(mode) = self.unix_mode {
+            write_field(out, TAG_UNIX_MODE, &mode.to_le_bytes());
+        }
+        if let Some(content_type) = &self.content_type {
+            write_field(out, TAG_CONTENT_TYPE, content_type.as_bytes());
+        }
+    }
+}
+
+/// How the key of a password-protected file is derived. Files protected with
+/// Shamir shares carry none.
+#[derive(Debug, Clone, PartialEq)]
+pub enum KeyDerivation {
+    Argon2id {
+        salt: [u8; SALT_LEN],
+        params: Argon2Params,
+    },
+    /// PBKDF2-HMAC-SHA256, for where Argon2id isn't allowed.
+    Pbkdf2Sha256 {
+        salt: [u8; SALT_LEN],
+        iterations: u32,
+    },
+    Scrypt {
+        salt: [u8; SALT_LEN],
+        params: ScryptParams,
+    },
+}
+
+impl KeyDerivation {
+    pub fn name(&self) -> &'static str {
+        match self {
+            KeyDerivation::Argon2id { .. } => "argon2id",
+            KeyDerivation::Pbkdf2Sha256 { .. } => "pbkdf2-sha256",
+            KeyDerivation::Scrypt { .. } => "scrypt",
+        }
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            KeyDerivation::Argon2id { salt, params } => {
+                let mut bytes = vec![KDF_ARGON2ID];
+                bytes.extend_from_slice(salt);
+                bytes.extend_from_slice(&params.m_cost.to_le_bytes());
+                bytes.extend_from_slice(&params.t_cost.to_le_bytes());
+                bytes.extend_from_slice(&params.p_cost.to_le_bytes());
+                bytes
+            }
+            KeyDerivation::Pbkdf2Sha256 { salt, iterations } => {
+                let mut bytes = vec![KDF_PBKDF2_SHA256];
+                bytes.extend_from_slice(salt);
+                bytes.extend_from_slice(&iterations.to_le_bytes());
+                bytes
+            }
+            KeyDerivation::Scrypt { salt, params } => {
+                let mut bytes = vec![KDF_SCRYPT];
+                bytes.extend_from_slice(salt);
+                bytes.extend_from_slice(&params.n.to_le_bytes());
+                bytes.extend_from_slice(&params.r.to_le_bytes());
+                bytes.extend_from_slice(&params.p.to_le_bytes());
+                bytes
+            }
+        }
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, FormatError> {
+        match bytes.split_first() {
+            Some((&KDF_ARGON2ID, rest)) if rest.len() == SALT_LEN + 12 => {
+                let (salt, params) = rest.split_at(SALT_LEN);
+                let u32_at = |i: usize| {
+                    u32::from_le_bytes(params[i * 4..i * 4 + 4].try_into().expect("4-byte slice"))
+                };
+                Ok(KeyDerivation::Argon2id {
+                    salt: salt.try_into().expect("length checked above"),
+                    params: Argon2Params {
+                        m_cost: u32_at(0),
+                        t_cost: u32_at(1),
+                        p_cost: u32_at(2),
+                    },
+                })
+            }
+            Some((&KDF_PBKDF2_SHA256, rest)) if rest.len() == SALT_LEN + 4 => {
+                let (salt, iterations) = rest.split_at(SALT_LEN);
+                Ok(KeyDerivation::Pbkdf2Sha256 {
+                    salt: salt.try_into().expect("length checked above"),
+                    iterations: u32::from_le_bytes(iterations.try_into().expect("length checked above")),
+                })
+            }
+            Some((&KDF_SCRYPT, rest)) if rest.len() == SALT_LEN + 16 => {
+                let (salt, params) = rest.split_at(SALT_LEN);
+                let (n, rp) = params.split_at(8);
+                Ok(KeyDerivation::Scrypt {
+                    salt: salt.try_into().expect("length checked above"),
+                    params: ScryptParams {
+                        n: u64::from_le_bytes(n.try_into().expect("8-byte slice")),
+                        r: u32::from_le_bytes(rp[..4].try_into().expect("4-byte slice")),
+                        p: u32::from_le_bytes(rp[4..].try_into().expect("4-byte slice")),
+                    },
+                })
+            }
+            _ => Err(FormatError::InvalidField("key_derivation")),
+        }
+    }
+}
+
+/// How the key of a keyfile-protected file is derived (see
+/// [`crate::crypto::EncryptionKey::from_keyfile`]).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyfileDerivation {
+    pub salt: [u8; SALT_LEN],
+    /// The key also takes the secret split into the file's shares, so both
+    /// the keyfile and enough shares are needed.
+    pub with_shares: bool,
+    pub source: KeyfileSource,
+}
+
+/// Where the secret hashed in place of a keyfile's contents comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum KeyfileSource {
+    /// A file the user picks
+    #[default]
+    File,
+    /// A data object on a PIV smartcard (see [`crate::piv`])
+    Piv { object_id: u32 },
+}
+
+impl KeyfileDerivation {
+    fn to_bytes(self) -> Vec<u8> {
+        let mode = match (self.source, self.with_shares) {
+            (KeyfileSource::File, false) => KEYFILE_ALONE,
+            (KeyfileSource::File, true) => KEYFILE_AND_SHARES,
+            (KeyfileSource::Piv { .. }, false) => PIV_ALONE,
+            (KeyfileSource::Piv { .. }, true) => PIV_AND_SHARES,
+        };
+        let mut bytes = vec![mode];
+        bytes.extend_from_slice(&self.salt);
+        if let KeyfileSource::Piv { object_id } = self.source {
+            bytes.extend_from_slice(&object_id.to_le_bytes());
+        }
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, FormatError> {
+        let invalid = || FormatError::InvalidField("keyfile");
+        let (&mode, rest) = bytes.split_first().ok_or_else(invalid)?;
+        let (salt, source) = match mode {
+            KEYFILE_ALONE | KEYFILE_AND_SHARES if rest.len() == SALT_LEN => (rest, KeyfileSource::File),
+            PIV_ALONE | PIV_AND_SHARES if rest.len() == SALT_LEN + 4 => {
+                let (salt, object_id) = rest.split_at(SALT_LEN);
+                let object_id = u32::from_le_bytes(object_id.try_into().expect("4-byte slice"));
+                (salt, KeyfileSource::Piv { object_id })
+            }
+            _ => return Err(invalid()),
+        };
+        Ok(Self {
+            salt: salt.try_into().expect("length checked above"),
+            with_shares: matches!(mode, KEYFILE_AND_SHARES | PIV_AND_SHARES),
+            source,
+        })
+    }
+}
+
+/// Compression applied to the plaintext before it was encrypted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    Zstd,
+}
+
+impl Compression {
+    fn id(self) -> u8 {
+        match self {
+            Compression::Zstd => 1,
+        }
+    }
+
+    fn from_id(id: u8) -> Result<Self, FormatError> {
+        match id {
+            1 => Ok(Compression::Zstd),
+            _ => Err(FormatError::InvalidField("compression")),
+        }
+    }
+}
+
+/// Archive format of a plaintext that packs a whole directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    Tar,
+}
+
+impl ArchiveFormat {
+    fn id(self) -> u8 {
+        match self {
+            ArchiveFormat::Tar => 1,
+        }
+    }
+
+    fn from_id(id: u8) -> Result<Self, FormatError> {
+        match id {
+            1 => Ok(ArchiveFormat::Tar),
+            _ => Err(FormatError::InvalidField("archive")),
+        }
+    }
+}
+
+/// The k-of-n scheme the file key was split with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ShareThreshold {
+    pub k: u8,
+    pub n: u8,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileHeader {
+    pub magic: [u8; 4],
+    pub version: u8,
+    pub cipher: CipherAlgorithm,
+    /// [`CipherAlgorithm::nonce_len`] bytes; the field's length is checked
+    /// against the cipher when parsing.
+    pub nonce: Vec<u8>,
+    pub ciphertext_len: u64,
+    pub metadata: FileMetadata,
+    pub key_derivation: Option<KeyDerivation>,
+    pub share_threshold: Option<ShareThreshold>,
+    /// Set when the ciphertext is a sequence of independently authenticated
+    /// chunks (see [`crate::crypto::encrypt_chunks`]) rather than one AEAD
+    /// message. `nonce` is then the base nonce the chunk nonces derive from.
+    pub chunk_size: Option<u32>,
+    /// [`crate::crypto::EncryptionKey::check_value`] of the file key, so
+    /// shares or a password for another file can be told apart from damage.
+    pub key_check: Option<[u8; KEY_CHECK_LEN]>,
+    /// When the file was encrypted, in seconds since the Unix epoch.
+    pub created_at: Option<u64>,
+    /// Set when the plaintext was compressed before encryption. The
+    /// ciphertext then covers the compressed bytes, while the metadata
+    /// still describes the original file. Only whole-file ciphertexts are
+    /// compressed.
+    pub compression: Option<Compression>,
+    /// Set when the plaintext is an archive of a directory rather than a
+    /// single file.
+    pub archive: Option<ArchiveFormat>,
+    /// Caller-chosen string the file is bound to, such as a user or device
+    /// ID. It is authenticated with the rest of the header, and decryption
+    /// requires the caller to present it again.
+    pub context: Option<String>,
+    /// Version of CryptIt that wrote the file, for telling which build
+    /// produced a file in a bug report. Absent in older files.
+    pub producer: Option<String>,
+    /// Free-form note from whoever encrypted the file, shown by inspect.
+    /// Readable without the key but authenticated like every other field.
+    pub comment: Option<String>,
+    /// Random ID given to the file when it was encrypted. Its key shares
+    /// carry the same ID, so a share of some other file is recognised
+    /// before the key is reconstructed. Absent in older files.
+    pub file_id: Option<Uuid>,
+    /// The file key wrapped (see [`crate::crypto::wrap_key`]) under the key
+    /// derived from the password by `key_derivation`, for files that open
+    /// with either a password or shares. Without it, the derived key is the
+    /// file key itself.
+    pub wrapped_key: Option<Vec<u8>>,
+    /// Set when the key is derived from a keyfile, or a smartcard secret
+    /// hashed like one, rather than a password.
+    pub keyfile: Option<KeyfileDerivation>,
+    /// The file key sealed to each recipient's public key (see
+    /// [`crate::crypto::seal_key_to_recipient`]), one field per recipient.
+    pub recipients: Vec<Vec<u8>>,
+    /// Ed25519 public key of whoever signed the file. Its signature over the
+    /// serialized header and the ciphertext follows the ciphertext. Being in
+    /// the header, the key is authenticated like every other field, so
+    /// removing it makes decryption fail.
+    pub signer: Option<[u8; crypto::SIGNING_KEY_LEN]>,
+}
+
+impl FileHeader {
+    pub fn new(cipher: CipherAlgorithm, nonce: impl Into<Vec<u8>>, ciphertext_len: u64) -> Self {
+        Self {
+            magic: *MAGIC,
+            version: FORMAT_VERSION,
+            cipher,
+            nonce: nonce.into(),
+            ciphertext_len,
+            metadata: FileMetadata::default(),
+            key_derivation: None,
+            share_threshold: None,
+            chunk_size: None,
+            key_check: None,
+            created_at: None,
+            compression: None,
+            archive: None,
+            context: None,
+            producer: None,
+            comment: None,
+            file_id: None,
+            wrapped_key: None,
+            keyfile: None,
+            recipients: Vec::new(),
+            signer: None,
+        }
+    }
+
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = Some(compression);
+        self
+    }
+
+    pub fn with_archive(mut self, archive: ArchiveFormat) -> Self {
+        self.archive = Some(archive);
+        self
+    }
+
+    pub fn with_context(mut self, context: &str) -> Self {
+        self.context = Some(context.to_string());
+        self
+    }
+
+    pub fn with_file_id(mut self, file_id: Uuid) -> Self {
+        self.file_id = Some(file_id);
+        self
+    }
+
+    pub fn with_producer(mut self, producer: &str) -> Self {
+        self.producer = Some(producer.to_string());
+        self
+    }
+
+    /// Fails when `comment` is longer than [`MAX_COMMENT_LEN`].
+    pub fn with_comment(mut self, comment: &str) -> Result<Self, FormatError> {
+        check_comment_len(comment)?;
+        self.comment = Some(comment.to_string());
+        Ok(self)
+    }
+
+    pub fn with_created_at(mut self, created_at: u64) -> Self {
+        self.created_at = Some(created_at);
+        self
+    }
+
+    pub fn with_key_check(mut self, key_check: [u8; KEY_CHECK_LEN]) -> Self {
+        self.key_check = Some(key_check);
+        self
+    }
+
+    pub fn with_chunk_size(mut self, chunk_size: u32) -> Self {
+        self.chunk_size = Some(chunk_size);
+        self
+    }
+
+    pub fn with_share_threshold(mut self, share_threshold: ShareThreshold) -> Self {
+        self.share_threshold = Some(share_threshold);
+        self
+    }
+
+    pub fn with_key_derivation(mut self, key_derivation: KeyDerivation) -> Self {
+        self.key_derivation = Some(key_derivation);
+        self
+    }
+
+    pub fn with_wrapped_key(mut self, wrapped_key: Vec<u8>) -> Self {
+        self.wrapped_key = Some(wrapped_key);
+        self
+    }
+
+    pub fn with_keyfile(mut self, keyfile: KeyfileDerivation) -> Self {
+        self.keyfile = Some(keyfile);
+        self
+    }
+
+    pub fn with_recipients(mut self, recipients: Vec<Vec<u8>>) -> Self {
+        self.recipients = recipients;
+        self
+    }
+
+    pub fn with_signer(mut self, signer: [u8; crypto::SIGNING_KEY_LEN]) -> Self {
+        self.signer = Some(signer);
+        self
+    }
+
+    pub fn with_metadata(mut self, metadata: FileMetadata) -> Self {
+        self.metadata = metadata;
+        self
+    }
+
+    /// Bytes to pass as AEAD associated data when encrypting or decrypting
+    /// the ciphertext that follows this header: the whole serialized header
+    /// except the nonce, which the cipher already authenticates as an input.
+    /// Changing any header field therefore makes decryption fail.
+    pub fn associated_data(&self) -> Vec<u8> {
+        let mut aad = Vec::new();
+        aad.extend_from_slice(&self.magic);
+        aad.push(self.version);
+        aad.extend_from_slice(&self.encode_fields(false));
+        aad
+    }
+
+    fn encode_fields(&self, include_nonce: bool) -> Vec<u8> {
+        let mut fields = Vec::new();
+        write_field(&mut fields, TAG_CIPHER, &[self.cipher.id()]);
+        if include_nonce {
+            write_field(&mut fields, TAG_NONCE, &self.nonce);
+        }
+        write_field(&mut fields, TAG_CIPHERTEXT_LEN, &self.ciphertext_len.to_le_bytes());
+        self.metadata.write_fields(&mut fields);
+        if let Some(key_derivation) = &self.key_derivation {
+            write_field(&mut fields, TAG_KEY_DERIVATION, &key_derivation.to_bytes());
+        }
+        if let Some(wrapped_key) = &self.wrapped_key {
+            write_field(&mut fields, TAG_WRAPPED_KEY, wrapped_key);
+        }
+        if let Some(keyfile) = self.keyfile {
+            write_field(&mut fields, TAG_KEYFILE, &keyfile.to_bytes());
+        }
+        for recipient in &self.recipients {
+            write_field(&mut fields, TAG_RECIPIENT, recipient);
+        }
+        if let Some(signer) = &self.signer {
+            write_field(&mut fields, TAG_SIGNER, signer);
+        }
+        if let Some(ShareThreshold { k, n }) = self.share_threshold {
+            write_field(&mut fields, TAG_SHARE_THRESHOLD, &[k, n]);
+        }
+        if let Some(file_id) = &self.file_id {
+            write_field(&mut fields, TAG_FILE_ID, file_id.as_bytes());
+        }
+        if let Some(chunk_size) = self.chunk_size {
+            write_field(&mut fields, TAG_CHUNK_SIZE, &chunk_size.to_le_bytes());
+        }
+        if let Some(key_check) = &self.key_check {
+            write_field(&mut fields, TAG_KEY_CHECK, key_check);
+        }
+        if let Some(created_at) = self.created_at {
+            write_field(&mut fields, TAG_CREATED_AT, &created_at.to_le_bytes());
+        }
+        if let Some(compression) = self.compression {
+            write_field(&mut fields, TAG_COMPRESSION, &[compression.id()]);
+        }
+        if let Some(archive) = self.archive {
+            write_field(&mut fields, TAG_ARCHIVE, &[archive.id()]);
+        }
+        if let Some(context) = &self.context {
+            write_field(&mut fields, TAG_CONTEXT, context.as_bytes());
+        }
+        if let Some(comment) = &self.comment {
+            write_field(&mut fields, TAG_COMMENT, comment.as_bytes());
+        }
+        if let Some(producer) = &self.producer {
+            write_field(&mut fields, TAG_PRODUCER, producer.as_bytes());
+        }
+        fields
+    }
+
+    /// Checks that the ciphertext following the header is exactly as long as
+    /// recorded, catching files that were cut short (e.g. by an interrupted
+    /// sync) before any decryption is attempted.
+    pub fn check_ciphertext_len(&self, actual: u64) -> Result<(), FormatError> {
+        let expected = self.expected_ciphertext_len()?;
+        if actual < expected {
+            return Err(FormatError::TruncatedCiphertext { expected, actual });
+        }
+        if actual != expected {
+            return Err(FormatError::CiphertextLengthMismatch { expected, actual });
+        }
+        Ok(())
+    }
+
+    /// The recorded ciphertext length, after checking that it agrees with
+    /// the recorded plaintext length when there is one.
+    fn expected_ciphertext_len(&self) -> Result<u64, FormatError> {
+        let Some(plaintext_len) = self.metadata.plaintext_len else {
+            return Ok(self.ciphertext_len);
+        };
+        // The size of a compressed plaintext says nothing about the ciphertext
+        if self.compression.is_some() {
+            return Ok(self.ciphertext_len);
+        }
+        let implied = match self.chunk_size {
+            Some(chunk_size) => crypto::chunked_ciphertext_len(plaintext_len, chunk_size as usize),
+            None => plaintext_len.saturating_add(TAG_LEN as u64),
+        };
+        if implied != self.ciphertext_len {
+            return Err(FormatError::InvalidField("plaintext_len"));
+        }
+        Ok(implied)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let fields = self.encode_fields(true);
+
+        let mut bytes = Vec::with_capacity(PREFIX_LEN + fields.len() + CHECKSUM_FIELD_LEN);
+        bytes.extend_from_slice(&self.magic);
+        bytes.push(self.version);
+        bytes.extend_from_slice(&((fields.len() + CHECKSUM_FIELD_LEN) as u32).to_le_bytes());
+        bytes.extend_from_slice(&fields);
+        let crc = crc32fast::hash(&bytes);
+        write_field(&mut bytes, TAG_HEADER_CRC, &crc.to_le_bytes());
+        bytes
+    }
+
+    /// Backup copy of the header written after the ciphertext:
+    /// `[header][header_len: u32 LE][TRAILER_MAGIC]`.
+    pub fn to_trailer_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.to_bytes();
+        let header_len = bytes.len() as u32;
+        bytes.extend_from_slice(&header_len.to_le_bytes());
+        bytes.extend_from_slice(TRAILER_MAGIC);
+        bytes
+    }
+
+    /// Parses the header at the start of `bytes`, returning it together with
+    /// the offset at which the ciphertext begins. Fields this build doesn't
+    /// understand are reported along with the version that wrote the file.
+    pub fn from_bytes(bytes: &[u8]) -> Result<(Self, usize), FormatError> {
+        Self::parse(bytes).map_err(|err| match err {
+            FormatError::InvalidField(_) | FormatError::MissingField(_) | FormatError::UnknownField(_) => match peek_producer(bytes) {
+                Some(producer) if producer != PRODUCER_VERSION => FormatError::UnknownStructure {
+                    source: Box::new(err),
+                    producer,
+                },
+                _ => err,
+            },
+            err => err,
+        })
+    }
+
+    fn parse(bytes: &[u8]) -> Result<(Self, usize), FormatError> {
+        if bytes.len() < MAGIC.len() || &bytes[..MAGIC.len()] != MAGIC {
+            return Err(FormatError::NotCryptItFile);
+        }
+        if bytes.len() < PREFIX_LEN {
+            return Err(FormatError::TruncatedHeader);
+        }
+
+        let version = bytes[MAGIC.len()];
+        if version == 0 {
+            return Err(FormatError::UnsupportedVersion(version));
+        }
+        if version > FORMAT_VERSION {
+            return Err(FormatError::NewerVersion {
+                version,
+                producer: peek_producer(bytes),
+            });
+        }
+
+        let mut len_bytes = [0u8; 4];
+        len_bytes.copy_from_slice(&bytes[MAGIC.len() + 1..PREFIX_LEN]);
+        let header_len = u32::from_le_bytes(len_bytes) as usize;
+        check_field_len("header_len", header_len, MAX_HEADER_LEN)?;
+        let end = PREFIX_LEN
+            .checked_add(header_len)
+            .filter(|&end| end <= bytes.len())
+            .ok_or(FormatError::TruncatedHeader)?;
+
+        // Check for bit rot before trusting any field
+        let checksum_offset = checksum_offset(bytes, end);
+        let checksum_ok = checksum_offset
+            .map(|offset| crc32fast::hash(&bytes[..offset]).to_le_bytes() == bytes[offset + 3..end]);
+        if checksum_ok == Some(false) || (version >= 2 && checksum_ok.is_none()) {
+            let offset = first_malformed_field(&bytes[..end])
+                .or(checksum_offset)
+                .unwrap_or_else(|| end.saturating_sub(CHECKSUM_FIELD_LEN).max(PREFIX_LEN));
+            return Err(FormatError::HeaderCorrupted { offset });
+        }
+
+        // Files written before the cipher was recorded are AES-256-GCM
+        let mut cipher = CipherAlgorithm::Aes256Gcm;
+        let mut nonce = None;
+        let mut ciphertext_len = None;
+        let mut metadata = FileMetadata::default();
+        let mut key_derivation = None;
+        let mut share_threshold = None;
+        let mut chunk_size = None;
+        let mut key_check = None;
+        let mut created_at = None;
+        let mut compression = None;
+        let mut archive = None;
+        let mut context = None;
+        let mut producer = None;
+        let mut comment = None;
+        let mut file_id = None;
+        let mut wrapped_key = None;
+        let mut keyfile = None;
+        let mut recipients = Vec::new();
+        let mut signer = None;
+        let mut fields = &bytes[PREFIX_LEN..end];
+        while !fields.is_empty() {
+            let (tag, value, rest) = read_field(fields)?;
+            match tag {
+                TAG_CIPHER => {
+                    let [id] = value else {
+                        return Err(FormatError::InvalidField("cipher"));
+                    };
+                    cipher = CipherAlgorithm::from_id(*id)
+                        .map_err(|_| FormatError::InvalidField("cipher"))?;
+                }
+                // Its length is checked once the cipher is known
+                TAG_NONCE => nonce = Some(value.to_vec()),
+                TAG_CIPHERTEXT_LEN => {
+                    let value: [u8; 8] = value
+                        .try_into()
+                        .map_err(|_| FormatError::InvalidField("ciphertext_len"))?;
+                    ciphertext_len = Some(u64::from_le_bytes(value));
+                }
+                TAG_ORIGINAL_NAME => {
+                    check_field_len("original_name", value.len(), MAX_ORIGINAL_NAME_LEN)?;
+                    let name = std::str::from_utf8(value)
+                        .map_err(|_| FormatError::InvalidField("original_name"))?;
+                    metadata.original_name = Some(name.to_string());
+                }
+                TAG_ORIGINAL_EXTENSION => {
+                    check_field_len("original_extension", value.len(), MAX_ORIGINAL_NAME_LEN)?;
+                    let extension = std::str::from_utf8(value)
+                        .map_err(|_| FormatError::InvalidField("original_extension"))?;
+                    metadata.original_extension = Some(extension.to_string());
+                }
+                TAG_PLAINTEXT_SHA256 => {
+                    let digest: [u8; 32] = value
+                        .try_into()
+                        .map_err(|_| FormatError::InvalidField("plaintext_sha256"))?;
+                    metadata.plaintext_sha256 = Some(digest);
+                }
+                TAG_PLAINTEXT_LEN => {
+                    let value: [u8; 8] = value
+                        .try_into()
+                        .map_err(|_| FormatError::InvalidField("plaintext_len"))?;
+                    metadata.plaintext_len = Some(u64::from_le_bytes(value));
+                }
+                TAG_MODIFIED_AT => {
+                    // Seconds, then nanoseconds within the second
+                    let value: [u8; 12] = value
+                        .try_into()
+                        .map_err(|_| FormatError::InvalidField("modified_at"))?;
+                    let secs = u64::from_le_bytes(value[..8].try_into().expect("8-byte slice"));
+                    let nanos = u32::from_le_bytes(value[8..].try_into().expect("4-byte slice"));
+                    if nanos >= 1_000_000_000 {
+                        return Err(FormatError::InvalidField("modified_at"));
+                    }
+                    metadata.modified_at = Some(Duration::new(secs, nanos));
+                }
+                TAG_UNIX_MODE => {
+                    let value: [u8; 4] = value
+                        .try_into()
+                        .map_err(|_| FormatError::InvalidField("unix_mode"))?;
+                    metadata.unix_mode = Some(u32::from_le_bytes(value) & 0o777);
+                }
+                TAG_CONTENT_TYPE => {
+                    check_field_len("content_type", value.len(), MAX_LABEL_LEN)?;
+                    let content_type = std::str::from_utf8(value)
+                        .map_err(|_| FormatError::InvalidField("content_type"))?;
+                    metadata.content_type = Some(content_type.to_string());
+                }
+                TAG_KEY_DERIVATION => {
+                    key_derivation = Some(KeyDerivation::from_bytes(value)?);
+                }
+                TAG_SHARE_THRESHOLD => match *value {
+                    [k, n] if k > 0 && k <= n => share_threshold = Some(ShareThreshold { k, n }),
+                    _ => return Err(FormatError::InvalidField("share_threshold")),
+                },
+                TAG_CHUNK_SIZE => {
+                    let value: [u8; 4] = value
+                        .try_into()
+                        .map_err(|_| FormatError::InvalidField("chunk_size"))?;
+                    match u32::from_le_bytes(value) {
+                        size @ 1..=MAX_CHUNK_SIZE => chunk_size = Some(size),
+                        _ => return Err(FormatError::InvalidField("chunk_size")),
+                    }
+                }
+                TAG_KEY_CHECK => {
+                    let value: [u8; KEY_CHECK_LEN] = value
+                        .try_into()
+                        .map_err(|_| FormatError::InvalidField("key_check"))?;
+                    key_check = Some(value);
+                }
+                TAG_CREATED_AT => {
+                    let value: [u8; 8] = value
+                        .try_into()
+                        .map_err(|_| FormatError::InvalidField("created_at"))?;
+                    created_at = Some(u64::from_le_bytes(value));
+                }
+                TAG_COMPRESSION => {
+                    let [id] = value else {
+                        return Err(FormatError::InvalidField("compression"));
+                    };
+                    compression = Some(Compression::from_id(*id)?);
+                }
+                TAG_ARCHIVE => {
+                    let [id] = value else {
+                        return Err(FormatError::InvalidField("archive"));
+                    };
+                    archive = Some(ArchiveFormat::from_id(*id)?);
+                }
+                TAG_CONTEXT => {
+                    check_field_len("context", value.len(), MAX_CONTEXT_LEN)?;
+                    let value = std::str::from_utf8(value).map_err(|_| FormatError::InvalidField("context"))?;
+                    context = Some(value.to_string());
+                }
+                TAG_COMMENT => {
+                    check_field_len("comment", value.len(), MAX_COMMENT_LEN)?;
+                    let value = std::str::from_utf8(value).map_err(|_| FormatError::InvalidField("comment"))?;
+                    comment = Some(value.to_string());
+                }
+                TAG_PRODUCER => {
+                    check_field_len("producer", value.len(), MAX_LABEL_LEN)?;
+                    let value = std::str::from_utf8(value).map_err(|_| FormatError::InvalidField("producer"))?;
+                    producer = Some(value.to_string());
+                }
+                TAG_FILE_ID => {
+                    let value = Uuid::from_slice(value).map_err(|_| FormatError::InvalidField("file_id"))?;
+                    file_id = Some(value);
+                }
+                TAG_WRAPPED_KEY => {
+                    if value.len() != crypto::WRAPPED_KEY_LEN {
+                        return Err(FormatError::InvalidField("wrapped_key"));
+                    }
+                    wrapped_key = Some(value.to_vec());
+                }
+                TAG_KEYFILE => {
+                    keyfile = Some(KeyfileDerivation::from_bytes(value)?);
+                }
+                TAG_RECIPIENT => {
+                    if value.len() != crypto::RECIPIENT_SLOT_LEN {
+                        return Err(FormatError::InvalidField("recipient"));
+                    }
+                    recipients.push(value.to_vec());
+                }
+                TAG_SIGNER => {
+                    signer = Some(value.try_into().map_err(|_| FormatError::InvalidField("signer"))?);
+                }
+                // Already verified above; it has to be the last field
+                TAG_HEADER_CRC if !rest.is_empty() => {
+                    return Err(FormatError::InvalidField("header_crc"));
+                }
+                TAG_HEADER_CRC => {}
+                _ => return Err(FormatError::UnknownField(tag)),
+            }
+            fields = rest;
+        }
+
+        let nonce = nonce.ok_or(FormatError::MissingField("nonce"))?;
+        if nonce.len() != cipher.nonce_len() {
+            return Err(FormatError::InvalidField("nonce"));
+        }
+        let header = Self {
+            magic: *MAGIC,
+            version,
+            cipher,
+            nonce,
+            ciphertext_len: ciphertext_len.ok_or(FormatError::MissingField("ciphertext_len"))?,
+            metadata,
+            key_derivation,
+            share_threshold,
+            chunk_size,
+            key_check,
+            created_at,
+            compression,
+            archive,
+            context,
+            producer,
+            comment,
+            file_id,
+            wrapped_key,
+            keyfile,
+            recipients,
+            signer,
+        };
+        if header.compression.is_some() {
+            // The original size bounds decompression
+            if header.metadata.plaintext_len.is_none() {
+                return Err(FormatError::MissingField("plaintext_len"));
+            }
+            if header.chunk_size.is_some() {
+                return Err(FormatError::InvalidField("compression"));
+            }
+        }
+        // A key comes from a password or a keyfile, never both
+        if header.keyfile.is_some() && header.key_derivation.is_some() {
+            return Err(FormatError::InvalidField("keyfile"));
+        }
+        // Recipients are sealed a random key, never a derived one
+        if !header.recipients.is_empty() && (header.keyfile.is_some() || header.key_derivation.is_some()) {
+            return Err(FormatError::InvalidField("recipient"));
+        }
+        Ok((header, end))
+    }
+}
+
+/// The backup header found at the end of a file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trailer {
+    pub header: FileHeader,
+    /// Length of the encoded header, which is also where the ciphertext
+    /// starts since the leading header is an identical copy
+    pub header_len: usize,
+}
+
+impl Trailer {
+    /// Bytes the trailer takes up at the end of the file.
+    pub fn encoded_len(&self) -> usize {
+        self.header_len + TRAILER_SUFFIX_LEN
+    }
+
+    /// Finds the backup header at the end of `tail`, the last bytes of a
+    /// file. Returns `None` when there is no trailer or it is damaged.
+    pub fn parse(tail: &[u8]) -> Option<Self> {
+        let suffix_start = tail.len().checked_sub(TRAILER_SUFFIX_LEN)?;
+        let (rest, suffix) = tail.split_at(suffix_start);
+        if &suffix[4..] != TRAILER_MAGIC {
+            return None;
+        }
+        let header_len = u32::from_le_bytes(suffix[..4].try_into().expect("4-byte slice")) as usize;
+        let header_start = rest.len().checked_sub(header_len)?;
+        let (header, offset) = FileHeader::from_bytes(&rest[header_start..]).ok()?;
+        (offset == header_len).then_some(Self { header, header_len })
+    }
+}
+
+/// A parsed `.cryptit` file, borrowing the ciphertext from the input buffer.
+#[derive(Debug, PartialEq)]
+pub enum Container<'a> {
+    Current {
+        header: Box<FileHeader>,
+        ciphertext: &'a [u8],
+    },
+    /// Headerless `[nonce][ciphertext]` written by earlier releases. These
+    /// can't be told apart from arbitrary data until decryption is attempted.
+    Legacy(EncryptedData),
+}
+
+/// Validates the magic and version of `bytes` before splitting it into header
+/// and ciphertext, dropping the backup header if there is one. Input without
+/// the magic is parsed as a legacy file with [`parse_encrypted_file`].
+pub fn parse_container(bytes: &[u8]) -> Result<Container<'_>, FormatError> {
+    match FileHeader::from_bytes(bytes) {
+        Ok((header, offset)) => {
+            let end = Trailer::parse(bytes).map_or(bytes.len(), |trailer| bytes.len() - trailer.encoded_len());
+            let ciphertext = bytes.get(offset..end).unwrap_or_default();
+            header.check_ciphertext_len(ciphertext.len() as u64)?;
+            Ok(Container::Current {
+                header: Box::new(header),
+                ciphertext,
+            })
+        }
+        Err(FormatError::NotCryptItFile) => parse_encrypted_file(bytes).map(Container::Legacy),
+        Err(e) => Err(e),
+    }
+}
+
+/// Splits a headerless `[nonce][ciphertext]` file. Anything shorter than a
+/// nonce plus an authentication tag can't have been written by CryptIt.
+pub fn parse_encrypted_file(bytes: &[u8]) -> Result<EncryptedData, FormatError> {
+    if bytes.len() < MIN_LEGACY_LEN {
+        return Err(FormatError::Truncated {
+            actual: bytes.len() as u64,
+            minimum: MIN_LEGACY_LEN as u64,
+        });
+    }
+    let (nonce, ciphertext) = bytes.split_at(LEGACY_NONCE_LEN);
+    Ok(EncryptedData {
+        nonce: nonce.to_vec(),
+        ciphertext: ciphertext.to_vec(),
+    })
+}
+
+/// Wraps an encrypted file as text: [`ARMOR_BEGIN`], the base64 of `bytes` in
+/// lines of [`ARMOR_LINE_LEN`], then [`ARMOR_END`].
+pub fn armor(bytes: &[u8]) -> String {
+    let encoded = general_purpose::STANDARD.encode(bytes);
+    let mut text = String::with_capacity(encoded.len() + encoded.len() / ARMOR_LINE_LEN + 64);
+    text.push_str(ARMOR_BEGIN);
+    text.push('\n');
+    for line in encoded.as_bytes().chunks(ARMOR_LINE_LEN) {
+        // Base64 output is ASCII
+        text.push_str(std::str::from_utf8(line).expect("base64 is ASCII"));
+        text.push('\n');
+    }
+    text.push_str(ARMOR_END);
+    text.push('\n');
+    text
+}
+
+/// Whether `head`, the start of a file, is armored text.
+pub fn is_armored(head: &[u8]) -> bool {
+    head.trim_ascii_start().starts_with(ARMOR_BEGIN.as_bytes())
+}
+
+/// Reverses [`armor`]. Surrounding whitespace and CRLF line endings, as left
+/// by mail clients and editors, are tolerated.
+pub fn dearmor(text: &[u8]) -> Result<Vec<u8>, FormatError> {
+    let text = std::str::from_utf8(text).map_err(|_| FormatError::InvalidArmor("not UTF-8 text".to_string()))?;
+    let mut lines = text.lines().map(str::trim).filter(|line| !line.is_empty());
+    if lines.next() != Some(ARMOR_BEGIN) {
+        return Err(FormatError::InvalidArmor(format!("missing {}", ARMOR_BEGIN)));
+    }
+    let mut body = String::new();
+    for line in lines.by_ref() {
+        if line == ARMOR_END {
+            break;
+        }
+        body.push_str(line);
+    }
+    if !text.trim_end().ends_with(ARMOR_END) || lines.next().is_some() {
+        return Err(FormatError::InvalidArmor(format!("missing {}", ARMOR_END)));
+    }
+    general_purpose::STANDARD
+        .decode(body)
+        .map_err(|e| FormatError::InvalidArmor(e.to_string()))
+}
+
+/// The producer recorded in the header at the start of `bytes`, read without
+/// checking anything else, so it can be found in headers we can't parse.
+fn peek_producer(bytes: &[u8]) -> Option<String> {
+    let header_len = u32::from_le_bytes(bytes.get(MAGIC.len() + 1..PREFIX_LEN)?.try_into().ok()?) as usize;
+    let mut fields = bytes.get(PREFIX_LEN..)?;
+    fields = &fields[..header_len.min(fields.len())];
+    while let Ok((tag, value, rest)) = read_field(fields) {
+        if tag == TAG_PRODUCER {
+            return std::str::from_utf8(value).ok().map(str::to_string);
+        }
+        fields = rest;
+    }
+    None
+}
+
+fn written_by(producer: &Option<String>) -> String {
+    producer
+        .as_ref()
+        .map(|producer| format!(", written by CryptIt {}", producer))
+        .unwrap_or_default()
+}
+
+fn check_field_len(field: &'static str, len: usize, max: usize) -> Result<(), FormatError> {
+    if len > max {
+        return Err(FormatError::FieldTooLarge { field, len, max });
+    }
+    Ok(())
+}
+
+fn check_comment_len(comment: &str) -> Result<(), FormatError> {
+    if comment.len() > MAX_COMMENT_LEN {
+        return Err(FormatError::CommentTooLong {
+            len: comment.len(),
+            max: MAX_COMMENT_LEN,
+        });
+    }
+    Ok(())
+}
+
+/// Whether `name` can be used as a single file name inside a directory on
+/// any platform: no separators, no `.` or `..`, and not too long.
+pub fn is_safe_file_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_ORIGINAL_NAME_LEN
+        && name != "."
+        && name != ".."
+        && !name.contains(['/', '\\', '\0'])
+}
+
+fn write_field(out: &mut Vec<u8>, tag: u8, value: &[u8]) {
+    out.push(tag);
+    out.extend_from_slice(&(value.len() as u16).to_le_bytes());
+    out.extend_from_slice(value);
+}
+
+/// Offset of the checksum field when `bytes[..end]` ends with one.
+fn checksum_offset(bytes: &[u8], end: usize) -> Option<usize> {
+    let offset = end.checked_sub(CHECKSUM_FIELD_LEN).filter(|&offset| offset >= PREFIX_LEN)?;
+    (bytes[offset] == TAG_HEADER_CRC && bytes[offset + 1..offset + 3] == [4, 0]).then_some(offset)
+}
+
+/// Offset of the first field in `header` whose framing doesn't fit.
+fn first_malformed_field(header: &[u8]) -> Option<usize> {
+    let mut fields = &header[PREFIX_LEN..];
+    while !fields.is_empty() {
+        match read_field(fields) {
+            Ok((_, _, rest)) => fields = rest,
+            Err(_) => return Some(header.len() - fields.len()),
+        }
+    }
+    None
+}
+
+fn read_field(bytes: &[u8]) -> Result<(u8, &[u8], &[u8]), FormatError> {
+    if bytes.len() < 3 {
+        return Err(FormatError::TruncatedHeader);
+    }
+    let tag = bytes[0];
+    let len = u16::from_le_bytes([bytes[1], bytes[2]]) as usize;
+    let rest = &bytes[3..];
+    if rest.len() < len {
+        return Err(FormatError::TruncatedHeader);
+    }
+    Ok((tag, &rest[..len], &rest[len..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header_round_trip() {
+        let header = FileHeader::new(CipherAlgorithm::ChaCha20Poly1305, [7u8; 12], 10)
+            .with_key_check([4u8; KEY_CHECK_LEN])
+            .with_created_at(1_700_000_000)
+            .with_file_id(Uuid::new_v4());
+        let mut bytes = header.to_bytes();
+        let header_len = bytes.len();
+        bytes.extend_from_slice(b"ciphertext");
+
+        let (parsed, offset) = FileHeader::from_bytes(&bytes).unwrap();
+        assert_eq!(parsed, header);
+        assert_eq!(offset, header_len);
+        assert_eq!(&bytes[offset..], b"ciphertext");
+        assert!(parsed.check_ciphertext_len((bytes.len() - offset) as u64).is_ok());
+    }
+
+    #[test]
+    fn test_nonce_length_follows_cipher() {
+        let header = FileHeader::new(CipherAlgorithm::XChaCha20Poly1305, [7u8; 24], 10);
+        let (parsed, _) = FileHeader::from_bytes(&header.to_bytes()).unwrap();
+        assert_eq!(parsed.nonce, [7u8; 24]);
+
+        // A 96-bit nonce recorded for XChaCha20, or the reverse, is rejected
+        let mismatched = FileHeader::new(CipherAlgorithm::XChaCha20Poly1305, [7u8; 12], 10);
+        assert!(matches!(
+            FileHeader::from_bytes(&mismatched.to_bytes()),
+            Err(FormatError::InvalidField("nonce"))
+        ));
+        let mismatched = FileHeader::new(CipherAlgorithm::Aes256Gcm, [7u8; 24], 10);
+        assert!(matches!(
+            FileHeader::from_bytes(&mismatched.to_bytes()),
+            Err(FormatError::InvalidField("nonce"))
+        ));
+    }
+
+    #[test]
+    fn test_metadata_round_trip() {
+        let header = FileHeader::new(CipherAlgorithm::Aes256Gcm, [7u8; 12], 10).with_metadata(
+            FileMetadata {
+                original_name: Some("report.pdf".to_string()),
+                original_extension: Some("pdf".to_string()),
+                plaintext_sha256: Some([9u8; 32]),
+                plaintext_len: Some(1234),
+                modified_at: Some(Duration::new(1_700_000_000, 123_456_789)),
+                unix_mode: Some(0o640),
+                content_type: Some("application/pdf".to_string()),
+            },
+        );
+        let (parsed, _) = FileHeader::from_bytes(&header.to_bytes()).unwrap();
+        assert_eq!(parsed.metadata, header.metadata);
+        assert_eq!(parsed.metadata.original_name.as_deref(), Some("report.pdf"));
+        assert_eq!(parsed.metadata.original_extension.as_deref(), Some("pdf"));
+        assert_eq!(parsed.metadata.plaintext_sha256, Some([9u8; 32]));
+        assert_eq!(parsed.metadata.plaintext_len, Some(1234));
+        assert_eq!(parsed.associated_data(), header.associated_data());
+    }
+
+    #[test]
+    fn test_plaintext_len_detects_truncation() {
+        let header = FileHeader::new(CipherAlgorithm::Aes256Gcm, [7u8; 12], 100 + TAG_LEN as u64).with_metadata(
+            FileMetadata {
+                plaintext_len: Some(100),
+                ..Default::default()
+            },
+        );
+        assert!(header.check_ciphertext_len(116).is_ok());
+        assert_eq!(
+            header.check_ciphertext_len(90).unwrap_err().to_string(),
+            "File truncated: expected 116 bytes of ciphertext, found 90"
+        );
+        assert!(matches!(
+            header.check_ciphertext_len(120),
+            Err(FormatError::CiphertextLengthMismatch { expected: 116, actual: 120 })
+        ));
+
+        // The two recorded lengths have to agree
+        let mut inconsistent = header.clone();
+        inconsistent.metadata.plaintext_len = Some(99);
+        assert_eq!(inconsistent.check_ciphertext_len(116), Err(FormatError::InvalidField("plaintext_len")));
+    }
+
+    #[test]
+    fn test_compression_round_trip() {
+        let header = FileHeader::new(CipherAlgorithm::Aes256Gcm, [7u8; 12], 10)
+            .with_compression(Compression::Zstd)
+            .with_metadata(FileMetadata {
+                plaintext_len: Some(4096),
+                ..Default::default()
+            });
+        let (parsed, _) = FileHeader::from_bytes(&header.to_bytes()).unwrap();
+        assert_eq!(parsed.compression, Some(Compression::Zstd));
+        assert!(parsed.check_ciphertext_len(10).is_ok());
+
+        // Decompression needs the original size as a bound
+        let mut unbounded = header.clone();
+        unbounded.metadata.plaintext_len = None;
+        assert_eq!(
+            FileHeader::from_bytes(&unbounded.to_bytes()),
+            Err(FormatError::MissingField("plaintext_len"))
+        );
+    }
+
+    #[test]
+    fn test_archive_round_trip() {
+        let header = FileHeader::new(CipherAlgorithm::Aes256Gcm, [7u8; 12], 10).with_archive(ArchiveFormat::Tar);
+        let (parsed, _) = FileHeader::from_bytes(&header.to_bytes()).unwrap();
+        assert_eq!(parsed.archive, Some(ArchiveFormat::Tar));
+        assert_ne!(parsed.associated_data(), FileHeader::new(CipherAlgorithm::Aes256Gcm, [7u8; 12], 10).associated_data());
+    }
+
+    #[test]
+    fn test_context_round_trip() {
+        let header = FileHeader::new(CipherAlgorithm::Aes256Gcm, [7u8; 12], 10).with_context("user-42");
+        let (parsed, _) = FileHeader::from_bytes(&header.to_bytes()).unwrap();
+        assert_eq!(parsed.context.as_deref(), Some("user-42"));
+
+        let mut rebound = header.clone();
+        rebound.context = Some("user-43".to_string());
+        assert_ne!(rebound.associated_data(), header.associated_data());
+
+        let oversized = header.with_context(&"x".repeat(MAX_CONTEXT_LEN + 1));
+        assert_eq!(
+            FileHeader::from_bytes(&oversized.to_bytes()),
+            Err(FormatError::FieldTooLarge {
+                field: "context",
+                len: MAX_CONTEXT_LEN + 1,
+                max: MAX_CONTEXT_LEN
+            })
+        );
+    }
+
+    #[test]
+    fn test_comment_round_trip() {
+        let comment = "2023 returns, shares held by Alice/Bob/Carol";
+        let header = FileHeader::new(CipherAlgorithm::Aes256Gcm, [7u8; 12], 10)
+            .with_comment(comment)
+            .unwrap();
+        let (parsed, _) = FileHeader::from_bytes(&header.to_bytes()).unwrap();
+        assert_eq!(parsed.comment.as_deref(), Some(comment));
+
+        // Files without a comment still parse
+        let (parsed, _) = FileHeader::from_bytes(&FileHeader::new(CipherAlgorithm::Aes256Gcm, [7u8; 12], 10).to_bytes()).unwrap();
+        assert_eq!(parsed.comment, None);
+
+        let mut edited = header.clone();
+        edited.comment = Some("2024 returns".to_string());
+        assert_ne!(edited.associated_data(), header.associated_data());
+
+        assert!(header.clone().with_comment(&"x".repeat(MAX_COMMENT_LEN)).is_ok());
+        assert_eq!(
+            header.with_comment(&"x".repeat(MAX_COMMENT_LEN + 1)),
+            Err(FormatError::CommentTooLong {
+                len: MAX_COMMENT_LEN + 1,
+                max: MAX_COMMENT_LEN
+            })
+        );
+    }
+
+    #[test]
+    fn test_key_derivation_round_trip() {
+        let key_derivation = KeyDerivation::Argon2id {
+            salt: [5u8; SALT_LEN],
+            params: Argon2Params {
+                m_cost: 65536,
+                t_cost: 3,
+                p_cost: 4,
+            },
+        };
+        let header = FileHeader::new(CipherAlgorithm::Aes256Gcm, [7u8; 12], 10)
+            .with_key_derivation(key_derivation.clone());
+        let (parsed, _) = FileHeader::from_bytes(&header.to_bytes()).unwrap();
+        assert_eq!(parsed.key_derivation, Some(key_derivation));
+
+        for key_derivation in [
+            KeyDerivation::Pbkdf2Sha256 {
+                salt: [6u8; SALT_LEN],
+                iterations: 600_000,
+            },
+            KeyDerivation::Scrypt {
+                salt: [7u8; SALT_LEN],
+                params: ScryptParams::sensitive(),
+            },
+        ] {
+            let header = header.clone().with_key_derivation(key_derivation.clone());
+            let (parsed, _) = FileHeader::from_bytes(&header.to_bytes()).unwrap();
+            assert_eq!(parsed.key_derivation, Some(key_derivation));
+        }
+
+        // A wrapped key has exactly one length
+        let wrapped = header.clone().with_wrapped_key(vec![8u8; crypto::WRAPPED_KEY_LEN]);
+        let (parsed, _) = FileHeader::from_bytes(&wrapped.to_bytes()).unwrap();
+        assert_eq!(parsed, wrapped);
+        let short = header.clone().with_wrapped_key(vec![8u8; crypto::WRAPPED_KEY_LEN - 1]);
+        assert!(matches!(
+            FileHeader::from_bytes(&short.to_bytes()),
+            Err(FormatError::InvalidField("wrapped_key"))
+        ));
+
+        let plain = FileHeader::new(CipherAlgorithm::Aes256Gcm, [7u8; 12], 10);
+        for with_shares in [false, true] {
+            for source in [KeyfileSource::File, KeyfileSource::Piv { object_id: 0x5F_C120 }] {
+                let keyfile = plain.clone().with_keyfile(KeyfileDerivation {
+                    salt: [9u8; SALT_LEN],
+                    with_shares,
+                    source,
+                });
+                let (parsed, _) = FileHeader::from_bytes(&keyfile.to_bytes()).unwrap();
+                assert_eq!(parsed, keyfile);
+            }
+        }
+        let to_recipients = plain
+            .clone()
+            .with_recipients(vec![vec![1u8; crypto::RECIPIENT_SLOT_LEN], vec![2u8; crypto::RECIPIENT_SLOT_LEN]]);
+        let (parsed, _) = FileHeader::from_bytes(&to_recipients.to_bytes()).unwrap();
+        assert_eq!(parsed, to_recipients);
+        let short = plain.clone().with_recipients(vec![vec![1u8; crypto::RECIPIENT_SLOT_LEN - 1]]);
+        assert!(matches!(
+            FileHeader::from_bytes(&short.to_bytes()),
+            Err(FormatError::InvalidField("recipient"))
+        ));
+        let signed = plain.clone().with_signer([3u8; crypto::SIGNING_KEY_LEN]);
+        let (parsed, _) = FileHeader::from_bytes(&signed.to_bytes()).unwrap();
+        assert_eq!(parsed, signed);
+
+        let both = header.with_keyfile(KeyfileDerivation {
+            salt: [9u8; SALT_LEN],
+            with_shares: false,
+            source: KeyfileSource::File,
+        });
+        assert!(matches!(
+            FileHeader::from_bytes(&both.to_bytes()),
+            Err(FormatError::InvalidField("keyfile"))
+        ));
+    }
+
+    #[test]
+    fn test_associated_data_covers_header() {
+        let header = FileHeader::new(CipherAlgorithm::Aes256Gcm, [7u8; 12], 10)
+            .with_share_threshold(ShareThreshold { k: 2, n: 3 })
+            .with_metadata(FileMetadata {
+                original_name: Some("a.txt".to_string()),
+                original_extension: Some("txt".to_string()),
+                plaintext_sha256: Some([1u8; 32]),
+                ..Default::default()
+            });
+        let aad = header.associated_data();
+
+        let mut changed = header.clone();
+        changed.nonce = vec![8u8; 12];
+        assert_eq!(changed.associated_data(), aad, "nonce is authenticated by the cipher itself");
+
+        let mut changed = header.clone();
+        changed.cipher = CipherAlgorithm::ChaCha20Poly1305;
+        assert_ne!(changed.associated_data(), aad);
+
+        let mut changed = header.clone();
+        changed.ciphertext_len += 1;
+        assert_ne!(changed.associated_data(), aad);
+
+        let mut changed = header.clone();
+        changed.metadata.plaintext_sha256 = Some([2u8; 32]);
+        assert_ne!(changed.associated_data(), aad);
+
+        let mut changed = header.clone();
+        changed.metadata.plaintext_len = Some(0);
+        assert_ne!(changed.associated_data(), aad);
+
+        let mut changed = header.clone();
+        changed.metadata.original_extension = Some("exe".to_string());
+        assert_ne!(changed.associated_data(), aad);
+
+        let changed = header.clone().with_key_check([3u8; KEY_CHECK_LEN]);
+        assert_ne!(changed.associated_data(), aad);
+
+        let mut changed = header.clone();
+        changed.version += 1;
+        assert_ne!(changed.associated_data(), aad);
+
+        let changed = header.clone().with_key_derivation(KeyDerivation::Argon2id {
+            salt: [0u8; SALT_LEN],
+            params: Argon2Params::default(),
+        });
+        assert_ne!(changed.associated_data(), aad);
+    }
+
+    #[test]
+    fn test_share_threshold_is_authenticated() {
+        let header = FileHeader::new(CipherAlgorithm::Aes256Gcm, [7u8; 12], 10)
+            .with_share_threshold(ShareThreshold { k: 3, n: 5 });
+        let (parsed, _) = FileHeader::from_bytes(&header.to_bytes()).unwrap();
+        assert_eq!(parsed.share_threshold, Some(ShareThreshold { k: 3, n: 5 }));
+
+        let lowered = parsed.clone().with_share_threshold(ShareThreshold { k: 1, n: 5 });
+        assert_ne!(lowered.associated_data(), header.associated_data());
+    }
+
+    #[test]
+    fn test_chunk_size_round_trip() {
+        let header = FileHeader::new(CipherAlgorithm::Aes256Gcm, [7u8; 12], 10).with_chunk_size(4096);
+        let (parsed, _) = FileHeader::from_bytes(&header.to_bytes()).unwrap();
+        assert_eq!(parsed.chunk_size, Some(4096));
+        assert_ne!(header.associated_data(), FileHeader::new(CipherAlgorithm::Aes256Gcm, [7u8; 12], 10).associated_data());
+
+        let oversized = header.with_chunk_size(MAX_CHUNK_SIZE + 1);
+        assert_eq!(
+            FileHeader::from_bytes(&oversized.to_bytes()),
+            Err(FormatError::InvalidField("chunk_size"))
+        );
+    }
+
+    #[test]
+    fn test_original_name_edge_cases() {
+        let metadata = FileMetadata::for_path(Path::new("/home/user/report.pdf"));
+        assert_eq!(metadata.safe_original_name(), Some("report.pdf"));
+        assert_eq!(metadata.safe_original_extension(), Some("pdf"));
+        assert_eq!(FileMetadata::for_path(Path::new("Makefile")).original_extension, None);
+
+        // Too long to restore, but the extension is still kept
+        let long_name = format!("{}.txt", "a".repeat(MAX_ORIGINAL_NAME_LEN));
+        let metadata = FileMetadata::for_path(Path::new(&long_name));
+        assert_eq!(metadata.original_name, None);
+        assert_eq!(metadata.safe_original_extension(), Some("txt"));
+
+        for crafted in ["../../etc/passwd", "dir/file.txt", "..\\boot.ini", "..", ".", ""] {
+            let metadata = FileMetadata {
+                original_name: Some(crafted.to_string()),
+                ..Default::default()
+            };
+            assert_eq!(metadata.safe_original_name(), None, "{:?} should be rejected", crafted);
+            let metadata = FileMetadata {
+                original_extension: Some(crafted.to_string()),
+                ..Default::default()
+            };
+            assert_eq!(metadata.safe_original_extension(), None, "{:?} should be rejected", crafted);
+        }
+
+        // Files from before the name was recorded simply have none
+        let header = FileHeader::new(CipherAlgorithm::Aes256Gcm, [7u8; 12], 10);
+        let (parsed, _) = FileHeader::from_bytes(&header.to_bytes()).unwrap();
+        assert_eq!(parsed.metadata.safe_original_name(), None);
+    }
+
+    #[test]
+    fn test_partial_ciphertext() {
+        let header = FileHeader::new(CipherAlgorithm::Aes256Gcm, [7u8; 12], 64);
+        assert_eq!(
+            header.check_ciphertext_len(40),
+            Err(FormatError::TruncatedCiphertext {
+                expected: 64,
+                actual: 40
+            })
+        );
+    }
+
+    #[test]
+    fn test_wrong_magic() {
+        assert_eq!(
+            FileHeader::from_bytes(b"definitely not encrypted"),
+            Err(FormatError::NotCryptItFile)
+        );
+        assert_eq!(FileHeader::from_bytes(b""), Err(FormatError::NotCryptItFile));
+    }
+
+    #[test]
+    fn test_truncated_header() {
+        let bytes = FileHeader::new(CipherAlgorithm::Aes256Gcm, [1u8; 12], 0).to_bytes();
+        for len in MAGIC.len()..bytes.len() {
+            assert_eq!(
+                FileHeader::from_bytes(&bytes[..len]),
+                Err(FormatError::TruncatedHeader),
+                "header cut at {} bytes should be rejected",
+                len
+            );
+        }
+    }
+
+    #[test]
+    fn test_unsupported_version() {
+        let mut bytes = FileHeader::new(CipherAlgorithm::Aes256Gcm, [1u8; 12], 0).to_bytes();
+        bytes[MAGIC.len()] = 0;
+        assert_eq!(FileHeader::from_bytes(&bytes), Err(FormatError::UnsupportedVersion(0)));
+
+        bytes[MAGIC.len()] = FORMAT_VERSION + 1;
+        assert_eq!(
+            FileHeader::from_bytes(&bytes),
+            Err(FormatError::NewerVersion {
+                version: FORMAT_VERSION + 1,
+                producer: None
+            })
+        );
+
+        let mut bytes = FileHeader::new(CipherAlgorithm::Aes256Gcm, [1u8; 12], 0)
+            .with_producer("9.1.0")
+            .to_bytes();
+        bytes[MAGIC.len()] = FORMAT_VERSION + 1;
+        assert_eq!(
+            FileHeader::from_bytes(&bytes).unwrap_err().to_string(),
+            format!(
+                "File was created by a newer version of CryptIt (format version {}, written by CryptIt 9.1.0); update CryptIt to open it",
+                FORMAT_VERSION + 1
+            )
+        );
+    }
+
+    #[test]
+    fn test_producer_round_trip() {
+        let header = FileHeader::new(CipherAlgorithm::Aes256Gcm, [1u8; 12], 0).with_producer(PRODUCER_VERSION);
+        let (parsed, _) = FileHeader::from_bytes(&header.to_bytes()).unwrap();
+        assert_eq!(parsed.producer.as_deref(), Some(PRODUCER_VERSION));
+
+        // A cipher id from a later release, written by that release
+        let header = header.with_producer("9.1.0");
+        let mut bytes = header.to_bytes();
+        let cipher = bytes[PREFIX_LEN..].windows(4).position(|field| field == [TAG_CIPHER, 1, 0, 1]).unwrap();
+        bytes[PREFIX_LEN + cipher + 3] = 99;
+        let checksum = bytes.len() - 4;
+        let crc = crc32fast::hash(&bytes[..checksum - 3]);
+        bytes[checksum..].copy_from_slice(&crc.to_le_bytes());
+        assert_eq!(
+            FileHeader::from_bytes(&bytes).unwrap_err().to_string(),
+            "Invalid header field: cipher, written by CryptIt 9.1.0"
+        );
+    }
+
+    #[test]
+    fn test_unknown_field_is_rejected() {
+        let header = FileHeader::new(CipherAlgorithm::Aes256Gcm, [1u8; 12], 0).with_producer("9.1.0");
+        let mut bytes = header.to_bytes();
+        bytes.truncate(bytes.len() - CHECKSUM_FIELD_LEN);
+        write_field(&mut bytes, 0x7F, b"from the future");
+        let header_len = (bytes.len() - PREFIX_LEN + CHECKSUM_FIELD_LEN) as u32;
+        bytes[MAGIC.len() + 1..PREFIX_LEN].copy_from_slice(&header_len.to_le_bytes());
+        let crc = crc32fast::hash(&bytes);
+        write_field(&mut bytes, TAG_HEADER_CRC, &crc.to_le_bytes());
+        assert_eq!(
+            FileHeader::from_bytes(&bytes).unwrap_err().to_string(),
+            "Unknown header field 0x7f, written by CryptIt 9.1.0"
+        );
+    }
+
+    #[test]
+    fn test_header_bit_flips() {
+        let header = FileHeader::new(CipherAlgorithm::Aes256Gcm, [1u8; 12], 0)
+            .with_metadata(FileMetadata::for_path(Path::new("notes.txt")));
+        let mut bytes = header.to_bytes();
+        let header_len = bytes.len();
+        bytes.extend_from_slice(b"ciphertext");
+        let checksum_offset = header_len - CHECKSUM_FIELD_LEN;
+
+        for pos in 0..header_len {
+            for bit in 0..8 {
+                let mut damaged = bytes.clone();
+                damaged[pos] ^= 1 << bit;
+                let err = FileHeader::from_bytes(&damaged).unwrap_err();
+                match pos {
+                    0..4 => assert_eq!(err, FormatError::NotCryptItFile),
+                    4 => assert!(matches!(err, FormatError::UnsupportedVersion(_) | FormatError::NewerVersion { .. })),
+                    5..PREFIX_LEN => assert!(
+                        matches!(
+                            err,
+                            FormatError::HeaderCorrupted { .. }
+                                | FormatError::TruncatedHeader
+                                | FormatError::FieldTooLarge { field: "header_len", .. }
+                        ),
+                        "header_len bit flip at byte {} gave {:?}",
+                        pos,
+                        err
+                    ),
+                    _ => assert!(
+                        matches!(err, FormatError::HeaderCorrupted { offset } if offset < header_len),
+                        "bit flip at byte {} gave {:?}",
+                        pos,
+                        err
+                    ),
+                }
+            }
+        }
+
+        // A damaged value is pinned to the checksum, a damaged length to the
+        // field it breaks
+        let name = bytes.windows(9).position(|window| window == b"notes.txt").unwrap();
+        let mut damaged = bytes.clone();
+        damaged[name] = b'N';
+        assert_eq!(
+            FileHeader::from_bytes(&damaged),
+            Err(FormatError::HeaderCorrupted { offset: checksum_offset })
+        );
+        let mut damaged = bytes.clone();
+        damaged[name - 1] = 0xFF;
+        assert_eq!(
+            FileHeader::from_bytes(&damaged),
+            Err(FormatError::HeaderCorrupted { offset: name - 3 })
+        );
+    }
+
+    #[test]
+    fn test_version_1_checksum_is_optional() {
+        let mut header = FileHeader::new(CipherAlgorithm::Aes256Gcm, [1u8; 12], 0);
+        header.version = 1;
+        let fields = header.encode_fields(true);
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(1);
+        bytes.extend_from_slice(&(fields.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&fields);
+        assert_eq!(FileHeader::from_bytes(&bytes), Ok((header.clone(), bytes.len())));
+
+        // The same header is corrupted when it claims to be version 2
+        bytes[MAGIC.len()] = 2;
+        assert!(matches!(FileHeader::from_bytes(&bytes), Err(FormatError::HeaderCorrupted { .. })));
+
+        // A version 1 header that has a checksum must match it
+        let mut bytes = header.to_bytes();
+        assert!(FileHeader::from_bytes(&bytes).is_ok());
+        bytes[PREFIX_LEN + 3] ^= 1;
+        assert!(matches!(FileHeader::from_bytes(&bytes), Err(FormatError::HeaderCorrupted { .. })));
+    }
+
+    #[test]
+    fn test_trailer_round_trip() {
+        let header = FileHeader::new(CipherAlgorithm::Aes256Gcm, [1u8; 12], 10).with_created_at(1_700_000_000);
+        let mut file = header.to_bytes();
+        file.extend_from_slice(b"ciphertext");
+        file.extend_from_slice(&header.to_trailer_bytes());
+        match parse_container(&file).unwrap() {
+            Container::Current { ciphertext, .. } => assert_eq!(ciphertext, b"ciphertext"),
+            Container::Legacy(_) => panic!("expected a current file"),
+        }
+
+        let trailer = Trailer::parse(&file).unwrap();
+        assert_eq!(trailer.header, header);
+        assert_eq!(trailer.header_len, header.to_bytes().len());
+        assert_eq!(trailer.encoded_len(), header.to_trailer_bytes().len());
+
+        // Damaged or missing trailers are ignored
+        let mut damaged = file.clone();
+        let pos = damaged.len() - trailer.encoded_len() + PREFIX_LEN;
+        damaged[pos] ^= 0x01;
+        assert_eq!(Trailer::parse(&damaged), None);
+        assert_eq!(Trailer::parse(&file[..file.len() - 1]), None);
+        assert_eq!(Trailer::parse(b"CRYT"), None);
+    }
+
+    #[test]
+    fn test_parse_container_errors() {
+        // Too short to be either a current or a legacy file
+        let err = parse_container(b"CRY").unwrap_err();
+        assert_eq!(err, FormatError::Truncated { actual: 3, minimum: 28 });
+
+        // Magic present but the header stops early
+        let bytes = FileHeader::new(CipherAlgorithm::Aes256Gcm, [1u8; 12], 0).to_bytes();
+        let err = parse_container(&bytes[..bytes.len() - 1]).unwrap_err();
+        assert_eq!(err, FormatError::TruncatedHeader);
+
+        // A file from a newer release
+        let mut bytes = FileHeader::new(CipherAlgorithm::Aes256Gcm, [1u8; 12], 0).to_bytes();
+        bytes[MAGIC.len()] = 3;
+        let err = parse_container(&bytes).unwrap_err();
+        assert!(err.to_string().starts_with("File was created by a newer version of CryptIt"), "unexpected error: {}", err);
+    }
+
+    #[test]
+    fn test_armor_round_trip() {
+        let bytes: Vec<u8> = (0..=255).cycle().take(1000).collect();
+        let text = armor(&bytes);
+        assert!(text.starts_with(ARMOR_BEGIN));
+        assert!(text.lines().all(|line| line.len() <= ARMOR_LINE_LEN || line.starts_with("-----")));
+        assert!(is_armored(text.as_bytes()));
+        assert!(!is_armored(&bytes));
+        assert_eq!(dearmor(text.as_bytes()).unwrap(), bytes);
+
+        // CRLF, a mix of line endings and stray whitespace all decode the same
+        let crlf = text.replace('\n', "\r\n");
+        assert_eq!(dearmor(crlf.as_bytes()).unwrap(), bytes);
+        let mixed: String = text
+            .lines()
+            .enumerate()
+            .map(|(i, line)| if i % 2 == 0 { format!("{}  \r\n", line) } else { format!("{}\n", line) })
+            .collect();
+        assert_eq!(dearmor(format!("\n\n{}\n\t", mixed).as_bytes()).unwrap(), bytes);
+        assert_eq!(dearmor(armor(b"").as_bytes()).unwrap(), b"");
+    }
+
+    #[test]
+    fn test_armor_rejects_corruption() {
+        let text = armor(b"some encrypted bytes");
+        let corrupted = text.replacen("c29t", "c2!t", 1);
+        assert!(matches!(dearmor(corrupted.as_bytes()), Err(FormatError::InvalidArmor(_))));
+
+        let truncated = &text[..text.len() - ARMOR_END.len() - 1];
+        assert_eq!(
+            dearmor(truncated.as_bytes()),
+            Err(FormatError::InvalidArmor(format!("missing {}", ARMOR_END)))
+        );
+        assert!(matches!(dearmor(b"c29tZQ==\n"), Err(FormatError::InvalidArmor(_))));
+    }
+
+    #[test]
+    fn test_parse_container_legacy() {
+        let mut bytes = vec![9u8; LEGACY_NONCE_LEN];
+        bytes.extend_from_slice(b"ciphertext and tag");
+        match parse_container(&bytes).unwrap() {
+            Container::Legacy(encrypted) => {
+                assert_eq!(encrypted.nonce, [9u8; LEGACY_NONCE_LEN]);
+                assert_eq!(encrypted.ciphertext, b"ciphertext and tag");
+            }
+            other => panic!("expected legacy container, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_encrypted_file_minimum_length() {
+        let err = parse_encrypted_file(&[]).unwrap_err();
+        assert_eq!(err, FormatError::Truncated { actual: 0, minimum: 28 });
+
+        // A nonce and a partial tag
+        let err = parse_encrypted_file(&[0u8; 13]).unwrap_err();
+        assert_eq!(err.to_string(), "File is truncated: 13 bytes, expected at least 28");
+
+        // A nonce and the tag of an empty plaintext
+        let encrypted = parse_encrypted_file(&[0u8; MIN_LEGACY_LEN]).unwrap();
+        assert_eq!(encrypted.ciphertext.len(), TAG_LEN);
+    }
+
+    /// Frames `fields` as a header with a valid checksum, so parsing gets
+    /// past the CRC and has to deal with the field values themselves.
+    fn raw_header(fields: &[u8]) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(FORMAT_VERSION);
+        bytes.extend_from_slice(&((fields.len() + CHECKSUM_FIELD_LEN) as u32).to_le_bytes());
+        bytes.extend_from_slice(fields);
+        let crc = crc32fast::hash(&bytes);
+        write_field(&mut bytes, TAG_HEADER_CRC, &crc.to_le_bytes());
+        bytes
+    }
+
+    #[test]
+    fn test_adversarial_lengths() {
+        let mut prefix = MAGIC.to_vec();
+        prefix.push(FORMAT_VERSION);
+
+        // A header length far beyond any real header
+        let mut huge = prefix.clone();
+        huge.extend_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(
+            FileHeader::from_bytes(&huge),
+            Err(FormatError::FieldTooLarge {
+                field: "header_len",
+                len: u32::MAX as usize,
+                max: MAX_HEADER_LEN
+            })
+        );
+
+        // A plausible header length that runs past the end of the input
+        let mut past_eof = prefix.clone();
+        past_eof.extend_from_slice(&1000u32.to_le_bytes());
+        past_eof.extend_from_slice(&[0u8; 10]);
+        assert!(FileHeader::from_bytes(&past_eof).is_err());
+
+        // A field whose length runs past the end of the header
+        let mut fields = vec![TAG_ORIGINAL_NAME];
+        fields.extend_from_slice(&u16::MAX.to_le_bytes());
+        fields.extend_from_slice(b"short");
+        assert!(FileHeader::from_bytes(&raw_header(&fields)).is_err());
+
+        // Well-framed fields that are longer than their caps
+        for (tag, field, max) in [
+            (TAG_ORIGINAL_NAME, "original_name", MAX_ORIGINAL_NAME_LEN),
+            (TAG_ORIGINAL_EXTENSION, "original_extension", MAX_ORIGINAL_NAME_LEN),
+            (TAG_CONTENT_TYPE, "content_type", MAX_LABEL_LEN),
+            (TAG_PRODUCER, "producer", MAX_LABEL_LEN),
+            (TAG_CONTEXT, "context", MAX_CONTEXT_LEN),
+            (TAG_COMMENT, "comment", MAX_COMMENT_LEN),
+        ] {
+            let mut fields = Vec::new();
+            write_field(&mut fields, tag, &vec![b'a'; max + 1]);
+            let err = FileHeader::parse(&raw_header(&fields)).unwrap_err();
+            assert_eq!(err, FormatError::FieldTooLarge { field, len: max + 1, max });
+        }
+
+        // Zero-length values for every tag, alone and all together
+        let mut all = Vec::new();
+        for tag in 0..=u8::MAX {
+            let mut fields = Vec::new();
+            write_field(&mut fields, tag, &[]);
+            all.extend_from_slice(&fields);
+            let _ = FileHeader::from_bytes(&raw_header(&fields));
+        }
+        let _ = FileHeader::from_bytes(&raw_header(&all));
+        let _ = FileHeader::from_bytes(&raw_header(&[]));
+
+        // Truncated and empty input anywhere a length is read
+        for bytes in [&[][..], &prefix, &huge, &past_eof, &[0xFF; 64]] {
+            let _ = FileHeader::from_bytes(bytes);
+            let _ = Trailer::parse(bytes);
+            let _ = parse_container(bytes);
+            let _ = parse_encrypted_file(bytes);
+        }
+        let mut trailer = vec![0u8; 4];
+        trailer.extend_from_slice(&u32::MAX.to_le_bytes());
+        trailer.extend_from_slice(TRAILER_MAGIC);
+        assert!(Trailer::parse(&trailer).is_none());
+    }
+}
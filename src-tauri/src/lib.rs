@@ -1,139 +1,830 @@
-use serde::{Deserialize, Serialize};
-use std::fs;
-use std::path::{Path, PathBuf};
+use base64::{engine::general_purpose, Engine};
+use serde::Serialize;
+use std::path::Path;
+use tauri::{AppHandle, Emitter};
+use zeroize::Zeroizing;
 
-mod crypto;
-mod sss;
+pub mod crypto;
+pub mod error;
+pub mod file_ops;
+pub mod format;
+pub mod mime;
+pub mod piv;
+pub mod qr;
+pub mod sss;
 
-use crypto::{EncryptionKey, encrypt_data, decrypt_data};
-use sss::{split_secret, reconstruct_secret};
+use crypto::{Argon2Params, CipherAlgorithm, PasswordKdf};
+pub use error::AppError;
+pub use file_ops::{
+    BatchEncryptionResult, DecryptOptions, DecryptionResult, EncryptOptions, EncryptionResult, FileEncryptionOutcome, FileInfo, KdfCalibration, Keypair, ProgressPayload,
+    OverwritePolicy, ProgressPhase, Protection, SignatureInfo, SigningKey, TextEncryptionResult, DEFAULT_STREAM_THRESHOLD,
+};
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct EncryptionResult {
-    pub shares: Vec<String>,
-    pub encrypted_file_path: String,
-}
+/// Event emitted with a [`ProgressPayload`] while a file is encrypted.
+pub const ENCRYPT_PROGRESS_EVENT: &str = "encrypt-progress";
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct DecryptionResult {
-    pub output_path: String,
-}
+/// Event emitted with a [`ProgressPayload`] while a file is decrypted.
+pub const DECRYPT_PROGRESS_EVENT: &str = "decrypt-progress";
 
+// Each argument is a field of the frontend's invoke payload. With
+// `protection` "password" the key is derived from `password` with Argon2id
+// and `k`, `n` and `custodians` are ignored; "hybrid" uses both. "keyfile"
+// derives it from the file at `keyfile_path` instead, and
+// "keyfile_and_shares" needs that file and the shares together. "piv" and
+// "piv_and_shares" do the same with a secret read from a PIV smartcard,
+// unlocked with `piv_pin`. "recipients" seals the key to each base64 public
+// key in `recipients`. With `sign`, a base64 private key from
+// `generate_signing_key`, the file is also signed, whatever protects it.
+// `overwrite_policy` is "overwrite", "fail" or "auto_rename"; without it,
+// `overwrite` chooses between the first two as before.
+#[allow(clippy::too_many_arguments)]
 #[tauri::command]
 async fn encrypt_file(
+    app: AppHandle,
     file_path: String,
     output_dir: String,
     k: u8,
     n: u8,
-) -> Result<EncryptionResult, String> {
-    println!("Encrypting file: {} to directory: {} with {}-of-{} sharing", file_path, output_dir, k, n);
-    
-    // Read the input file
-    let file_data = fs::read(&file_path)
-        .map_err(|e| format!("Failed to read file: {}", e))?;
-    
-    // Generate encryption key
-    let key = EncryptionKey::generate();
-    
-    // Encrypt the file data
-    let encrypted_data = encrypt_data(&file_data, &key)
-        .map_err(|e| format!("Encryption failed: {}", e))?;
-    
-    // Split the key using Shamir Secret Sharing
-    let shares = split_secret(key.as_bytes(), k, n)
-        .map_err(|e| format!("Failed to generate shares: {}", e))?;
-    
-    // Create output file path
-    let input_path = Path::new(&file_path);
-    let file_name = input_path
-        .file_stem()
-        .and_then(|s| s.to_str())
-        .unwrap_or("encrypted");
-    let output_path = PathBuf::from(&output_dir).join(format!("{}.cryptit", file_name));
-    
-    // Create a simple file format: [nonce][ciphertext]
-    let mut file_content = Vec::new();
-    file_content.extend_from_slice(&encrypted_data.nonce);
-    file_content.extend_from_slice(&encrypted_data.ciphertext);
-    
-    // Write encrypted file
-    fs::write(&output_path, &file_content)
-        .map_err(|e| format!("Failed to write encrypted file: {}", e))?;
-    
-    Ok(EncryptionResult {
-        shares,
-        encrypted_file_path: output_path.to_string_lossy().to_string(),
+    cipher: Option<String>,
+    stream_threshold: Option<u64>,
+    custodians: Option<Vec<String>>,
+    compress: Option<bool>,
+    armor: Option<bool>,
+    context: Option<String>,
+    comment: Option<String>,
+    prefer_siv: Option<bool>,
+    output_name: Option<String>,
+    overwrite: Option<bool>,
+    overwrite_policy: Option<OverwritePolicy>,
+    checksum_file: Option<bool>,
+    protection: Option<Protection>,
+    password: Option<String>,
+    keyfile_path: Option<String>,
+    recipients: Option<Vec<String>>,
+    piv_pin: Option<String>,
+    sign: Option<String>,
+) -> Result<EncryptionResult, AppError> {
+    let protection = protection.unwrap_or_default();
+    match protection {
+        Protection::Shares => println!("Encrypting file: {} to directory: {} with {}-of-{} sharing", file_path, output_dir, k, n),
+        Protection::Password => println!("Encrypting file: {} to directory: {} with a password", file_path, output_dir),
+        Protection::Hybrid => println!(
+            "Encrypting file: {} to directory: {} with a password or {}-of-{} sharing",
+            file_path, output_dir, k, n
+        ),
+        Protection::Keyfile => println!("Encrypting file: {} to directory: {} with a keyfile", file_path, output_dir),
+        Protection::KeyfileAndShares => println!(
+            "Encrypting file: {} to directory: {} with a keyfile and {}-of-{} sharing",
+            file_path, output_dir, k, n
+        ),
+        Protection::Piv => println!("Encrypting file: {} to directory: {} with a PIV smartcard", file_path, output_dir),
+        Protection::PivAndShares => println!(
+            "Encrypting file: {} to directory: {} with a PIV smartcard and {}-of-{} sharing",
+            file_path, output_dir, k, n
+        ),
+        Protection::Recipients => println!(
+            "Encrypting file: {} to directory: {} for {} recipients",
+            file_path,
+            output_dir,
+            recipients.as_ref().map_or(0, Vec::len)
+        ),
+    }
+    
+    let options = EncryptOptions {
+        cipher: parse_cipher(cipher, prefer_siv.unwrap_or(false))?,
+        stream_threshold: stream_threshold.unwrap_or(DEFAULT_STREAM_THRESHOLD),
+        compress: compress.unwrap_or(false),
+        armor: armor.unwrap_or(false),
+        context,
+        comment,
+        output_name,
+        overwrite_policy: overwrite_policy.unwrap_or_else(|| overwrite.unwrap_or(false).into()),
+        checksum_file: checksum_file.unwrap_or(false),
+        signing_key: sign.map(Zeroizing::new).as_deref().map(|key| SigningKey::from_base64(key)).transpose()?,
+        ..Default::default()
+    };
+    let password = Zeroizing::new(password.unwrap_or_default());
+    let custodians = custodians.unwrap_or_default();
+    let recipients = recipients.unwrap_or_default();
+    let keyfile_path = match (protection, keyfile_path) {
+        (Protection::Keyfile | Protection::KeyfileAndShares, None) => {
+            return Err(AppError::Crypto("This protection needs a keyfile".to_string()))
+        }
+        (_, keyfile_path) => keyfile_path.unwrap_or_default(),
+    };
+    let piv_pin = Zeroizing::new(piv_pin.unwrap_or_default());
+    run_blocking(move || {
+        let mut progress = emit_progress(&app, ENCRYPT_PROGRESS_EVENT);
+        match protection {
+            Protection::Shares => file_ops::encrypt_file(&file_path, &output_dir, k, n, &custodians, &options, &mut progress),
+            Protection::Password => file_ops::encrypt_file_with_password(
+                &file_path,
+                &output_dir,
+                &password,
+                PasswordKdf::Argon2id,
+                &options,
+                &mut progress,
+            ),
+            Protection::Hybrid => file_ops::encrypt_file_hybrid(
+                &file_path,
+                &output_dir,
+                &password,
+                k,
+                n,
+                &custodians,
+                &options,
+                &mut progress,
+            ),
+            Protection::Keyfile => file_ops::encrypt_file_with_keyfile(&file_path, &output_dir, &keyfile_path, &options, &mut progress),
+            Protection::KeyfileAndShares => file_ops::encrypt_file_with_keyfile_and_shares(
+                &file_path,
+                &output_dir,
+                &keyfile_path,
+                k,
+                n,
+                &custodians,
+                &options,
+                &mut progress,
+            ),
+            Protection::Piv => file_ops::encrypt_file_with_piv(
+                &file_path,
+                &output_dir,
+                piv::open_card(&piv_pin)?.as_mut(),
+                piv::DEFAULT_OBJECT_ID,
+                &options,
+                &mut progress,
+            ),
+            Protection::PivAndShares => file_ops::encrypt_file_with_piv_and_shares(
+                &file_path,
+                &output_dir,
+                piv::open_card(&piv_pin)?.as_mut(),
+                piv::DEFAULT_OBJECT_ID,
+                k,
+                n,
+                &custodians,
+                &options,
+                &mut progress,
+            ),
+            Protection::Recipients => {
+                file_ops::encrypt_file_for_recipients(&file_path, &output_dir, &recipients, &options, &mut progress)
+            }
+        }
     })
+    .await
 }
 
+#[allow(clippy::too_many_arguments)]
 #[tauri::command]
-async fn decrypt_file(
+async fn encrypt_file_with_password(
+    app: AppHandle,
     file_path: String,
     output_dir: String,
-    shares: Vec<String>,
-) -> Result<DecryptionResult, String> {
-    println!("Decrypting file: {} to directory: {} with {} shares", file_path, output_dir, shares.len());
-    
-    // Read the encrypted file
-    let encrypted_file_data = fs::read(&file_path)
-        .map_err(|e| format!("Failed to read encrypted file: {}", e))?;
+    password: String,
+    kdf: Option<String>,
+    cipher: Option<String>,
+    compress: Option<bool>,
+    armor: Option<bool>,
+    context: Option<String>,
+    comment: Option<String>,
+    output_name: Option<String>,
+    overwrite: Option<bool>,
+    checksum_file: Option<bool>,
+    memory_cost_kib: Option<u32>,
+    time_cost: Option<u32>,
+    parallelism: Option<u32>,
+) -> Result<EncryptionResult, AppError> {
+    println!("Encrypting file: {} to directory: {} with a password", file_path, output_dir);
     
-    // Parse the file format: [nonce][ciphertext]
-    if encrypted_file_data.len() < 12 {
-        return Err("Invalid encrypted file format".to_string());
+    let password = Zeroizing::new(password);
+    let options = EncryptOptions {
+        cipher: parse_cipher(cipher, false)?,
+        compress: compress.unwrap_or(false),
+        armor: armor.unwrap_or(false),
+        context,
+        comment,
+        output_name,
+        overwrite_policy: overwrite.unwrap_or(false).into(),
+        checksum_file: checksum_file.unwrap_or(false),
+        argon2_params: argon2_params(memory_cost_kib, time_cost, parallelism),
+        ..Default::default()
+    };
+    // "argon2id" (the default), "pbkdf2" or "scrypt"
+    let kdf = kdf.map_or(Ok(PasswordKdf::default()), |kdf| kdf.parse::<PasswordKdf>())?;
+    run_blocking(move || {
+        file_ops::encrypt_file_with_password(
+            &file_path,
+            &output_dir,
+            &password,
+            kdf,
+            &options,
+            &mut emit_progress(&app, ENCRYPT_PROGRESS_EVENT),
+        )
+    })
+    .await
+}
+
+/// Argon2id cost from the optional command arguments, each defaulting to
+/// [`Argon2Params::default`]. Checked when the key is derived.
+fn argon2_params(memory_cost_kib: Option<u32>, time_cost: Option<u32>, parallelism: Option<u32>) -> Argon2Params {
+    let default = Argon2Params::default();
+    Argon2Params {
+        m_cost: memory_cost_kib.unwrap_or(default.m_cost),
+        t_cost: time_cost.unwrap_or(default.t_cost),
+        p_cost: parallelism.unwrap_or(default.p_cost),
     }
+}
+
+/// Generates an X25519 keypair for receiving files encrypted to recipients
+#[tauri::command]
+fn generate_keypair() -> Keypair {
+    println!("Generating a recipient keypair");
+    file_ops::generate_keypair()
+}
+
+/// Generates an Ed25519 keypair for signing files with `encrypt_file`
+#[tauri::command]
+fn generate_signing_key() -> Keypair {
+    println!("Generating a signing keypair");
+    file_ops::generate_signing_key()
+}
+
+/// Suggests Argon2id parameters taking about `target_ms` to derive a key on
+/// this machine, to pass back to `encrypt_file_with_password`
+#[tauri::command]
+async fn calibrate_kdf(target_ms: u64) -> Result<KdfCalibration, AppError> {
+    println!("Calibrating Argon2id for {} ms", target_ms);
+    run_blocking(move || file_ops::calibrate_kdf(target_ms)).await
+}
+
+/// Encrypts each file with its own key and shares, resolving to one outcome
+/// per file so a bad file doesn't stop the rest.
+#[allow(clippy::too_many_arguments)]
+#[tauri::command]
+async fn encrypt_files(
+    app: AppHandle,
+    file_paths: Vec<String>,
+    output_dir: String,
+    k: u8,
+    n: u8,
+    cipher: Option<String>,
+    custodians: Option<Vec<String>>,
+    compress: Option<bool>,
+    prefer_siv: Option<bool>,
+    overwrite: Option<bool>,
+) -> Result<Vec<FileEncryptionOutcome>, AppError> {
+    println!("Encrypting {} files to directory: {} with {}-of-{} sharing each", file_paths.len(), output_dir, k, n);
     
-    let mut nonce = [0u8; 12];
-    nonce.copy_from_slice(&encrypted_file_data[0..12]);
-    let ciphertext = encrypted_file_data[12..].to_vec();
+    let options = EncryptOptions {
+        cipher: parse_cipher(cipher, prefer_siv.unwrap_or(false))?,
+        compress: compress.unwrap_or(false),
+        overwrite_policy: overwrite.unwrap_or(false).into(),
+        ..Default::default()
+    };
+    file_ops::encrypt_files(
+        &file_paths,
+        &output_dir,
+        k,
+        n,
+        &custodians.unwrap_or_default(),
+        &options,
+        &mut emit_progress(&app, ENCRYPT_PROGRESS_EVENT),
+    )
+}
+
+/// Encrypts every file under one key, resolving to a single set of shares
+/// that opens any of them.
+#[allow(clippy::too_many_arguments)]
+#[tauri::command]
+async fn encrypt_files_shared_key(
+    app: AppHandle,
+    file_paths: Vec<String>,
+    output_dir: String,
+    k: u8,
+    n: u8,
+    cipher: Option<String>,
+    custodians: Option<Vec<String>>,
+    compress: Option<bool>,
+    prefer_siv: Option<bool>,
+    overwrite: Option<bool>,
+) -> Result<BatchEncryptionResult, AppError> {
+    println!("Encrypting {} files to directory: {} with {}-of-{} sharing", file_paths.len(), output_dir, k, n);
     
-    let encrypted_data = crypto::EncryptedData {
-        nonce,
-        ciphertext,
+    let options = EncryptOptions {
+        cipher: parse_cipher(cipher, prefer_siv.unwrap_or(false))?,
+        compress: compress.unwrap_or(false),
+        overwrite_policy: overwrite.unwrap_or(false).into(),
+        ..Default::default()
     };
+    file_ops::encrypt_files_shared_key(
+        &file_paths,
+        &output_dir,
+        k,
+        n,
+        &custodians.unwrap_or_default(),
+        &options,
+        &mut emit_progress(&app, ENCRYPT_PROGRESS_EVENT),
+    )
+}
+
+#[tauri::command]
+async fn encrypt_directory(
+    dir_path: String,
+    output_dir: String,
+    k: u8,
+    n: u8,
+) -> Result<BatchEncryptionResult, AppError> {
+    println!("Encrypting directory: {} to directory: {} with {}-of-{} sharing", dir_path, output_dir, k, n);
     
-    // Reconstruct the key from shares
-    let key_bytes = reconstruct_secret(&shares)
-        .map_err(|e| format!("Failed to reconstruct key: {}", e))?;
-    
-    let key = EncryptionKey::from_bytes(&key_bytes)
-        .map_err(|e| format!("Invalid key: {}", e))?;
-    
-    // Decrypt the data
-    let decrypted_data = decrypt_data(&encrypted_data, &key)
-        .map_err(|e| format!("Decryption failed: {}", e))?;
-    
-    // Create output file path
-    let input_path = Path::new(&file_path);
-    let file_name = input_path
-        .file_stem()
-        .and_then(|s| s.to_str())
-        .unwrap_or("decrypted");
-    
-    // Remove .cryptit extension if present
-    let clean_name = if file_name.ends_with(".cryptit") {
-        &file_name[..file_name.len() - 8]
-    } else {
-        file_name
+    file_ops::encrypt_directory(&dir_path, &output_dir, k, n)
+}
+
+#[tauri::command]
+async fn encrypt_directory_archive(
+    dir_path: String,
+    output_dir: String,
+    k: u8,
+    n: u8,
+) -> Result<EncryptionResult, AppError> {
+    println!("Encrypting directory: {} as an archive to directory: {} with {}-of-{} sharing", dir_path, output_dir, k, n);
+    
+    file_ops::encrypt_directory_archive(&dir_path, &output_dir, k, n)
+}
+
+/// Parses the optional `cipher` command argument ("aes256gcm", "chacha20",
+/// "aes256gcmsiv" or "xchacha20"). Without one, `prefer_siv` picks
+/// AES-256-GCM-SIV over the default AES-256-GCM; it is meant for keys reused
+/// across many files.
+fn parse_cipher(cipher: Option<String>, prefer_siv: bool) -> Result<CipherAlgorithm, AppError> {
+    match cipher {
+        Some(name) => Ok(name.parse::<CipherAlgorithm>()?),
+        None if prefer_siv => Ok(CipherAlgorithm::Aes256GcmSiv),
+        None => Ok(CipherAlgorithm::default()),
+    }
+}
+
+/// Decrypts with `password` if one is given, otherwise with `private_key`,
+/// with the PIV smartcard unlocked by `piv_pin` and `shares`, with
+/// `keyfile_path` and `shares`, or with `shares` alone. A file protected
+/// another way is reported as such from its header.
+#[allow(clippy::too_many_arguments)]
+#[tauri::command]
+async fn decrypt_file(
+    app: AppHandle,
+    file_path: String,
+    output_dir: String,
+    shares: Vec<String>,
+    context: Option<String>,
+    output_name: Option<String>,
+    overwrite: Option<bool>,
+    checksum_file: Option<bool>,
+    password: Option<String>,
+    keyfile_path: Option<String>,
+    private_key: Option<String>,
+    piv_pin: Option<String>,
+) -> Result<DecryptionResult, AppError> {
+    let options = DecryptOptions {
+        context,
+        output_name,
+        overwrite: overwrite.unwrap_or(false),
+        checksum_file: checksum_file.unwrap_or(false),
     };
+    let password = password.map(Zeroizing::new);
+    let private_key = private_key.map(Zeroizing::new);
+    let piv_pin = piv_pin.map(Zeroizing::new);
+    if let (None, None, Some(piv_pin)) = (&password, &private_key, piv_pin) {
+        println!(
+            "Decrypting file: {} to directory: {} with a PIV smartcard and {} shares",
+            file_path,
+            output_dir,
+            shares.len()
+        );
+        return run_blocking(move || {
+            file_ops::decrypt_file_with_piv(
+                &file_path,
+                &output_dir,
+                piv::open_card(&piv_pin)?.as_mut(),
+                &shares,
+                &options,
+                &mut emit_progress(&app, DECRYPT_PROGRESS_EVENT),
+            )
+        })
+        .await;
+    }
+    match (&password, &private_key, &keyfile_path) {
+        (Some(_), _, _) => println!("Decrypting file: {} to directory: {} with a password", file_path, output_dir),
+        (None, Some(_), _) => println!("Decrypting file: {} to directory: {} with a private key", file_path, output_dir),
+        (None, None, Some(_)) => println!(
+            "Decrypting file: {} to directory: {} with a keyfile and {} shares",
+            file_path,
+            output_dir,
+            shares.len()
+        ),
+        (None, None, None) => println!("Decrypting file: {} to directory: {} with {} shares", file_path, output_dir, shares.len()),
+    }
+    
+    run_blocking(move || {
+        file_ops::decrypt_file_with_credentials(
+            &file_path,
+            &output_dir,
+            &shares,
+            password.as_deref().map(String::as_str),
+            keyfile_path.as_deref(),
+            private_key.as_deref().map(String::as_str),
+            &options,
+            &mut emit_progress(&app, DECRYPT_PROGRESS_EVENT),
+        )
+    })
+    .await
+}
+
+/// Like `decrypt_file`, with each share read from a file instead of passed in.
+#[allow(clippy::too_many_arguments)]
+#[tauri::command]
+async fn decrypt_file_from_share_files(
+    app: AppHandle,
+    file_path: String,
+    output_dir: String,
+    share_paths: Vec<String>,
+    context: Option<String>,
+    output_name: Option<String>,
+    overwrite: Option<bool>,
+    checksum_file: Option<bool>,
+) -> Result<DecryptionResult, AppError> {
+    println!("Decrypting file: {} to directory: {} with {} share files", file_path, output_dir, share_paths.len());
     
-    let output_path = PathBuf::from(&output_dir).join(format!("{}_decrypted.txt", clean_name));
+    file_ops::decrypt_file_from_share_files(
+        &file_path,
+        &output_dir,
+        &share_paths,
+        &DecryptOptions {
+            context,
+            output_name,
+            overwrite: overwrite.unwrap_or(false),
+            checksum_file: checksum_file.unwrap_or(false),
+        },
+        &mut emit_progress(&app, DECRYPT_PROGRESS_EVENT),
+    )
+}
+
+/// Dry run of `decrypt_file`: resolves to `true` when the shares decrypt the
+/// file and every authentication tag checks out, without writing anything.
+#[tauri::command]
+async fn verify_file(file_path: String, shares: Vec<String>, context: Option<String>) -> Result<bool, AppError> {
+    println!("Verifying file: {} with {} shares", file_path, shares.len());
     
-    // Write decrypted file
-    fs::write(&output_path, &decrypted_data)
-        .map_err(|e| format!("Failed to write decrypted file: {}", e))?;
+    file_ops::verify_file(&file_path, &shares, context.as_deref())
+}
+
+/// Splits the key of an encrypted file into a new set of `n` shares, any `k`
+/// of which decrypt it, without re-encrypting the file. Resolves to the new
+/// shares along with the path and unchanged SHA-256 of the file.
+#[tauri::command]
+async fn reshare_key(
+    file_path: String,
+    shares: Vec<String>,
+    new_k: u8,
+    new_n: u8,
+    custodians: Option<Vec<String>>,
+) -> Result<EncryptionResult, AppError> {
+    println!("Resharing key of file: {} as {}-of-{}", file_path, new_k, new_n);
     
-    Ok(DecryptionResult {
-        output_path: output_path.to_string_lossy().to_string(),
+    file_ops::reshare_key(&file_path, &shares, new_k, new_n, &custodians.unwrap_or_default())
+}
+
+/// Saves each share to its own `.share` file in `output_dir`, resolving to
+/// the paths written.
+#[tauri::command]
+async fn export_shares_as_files(shares: Vec<String>, output_dir: String) -> Result<Vec<String>, AppError> {
+    println!("Exporting {} shares to directory: {}", shares.len(), output_dir);
+    
+    file_ops::export_share_files(&shares, &output_dir)
+}
+
+/// Reads shares back from `.share` files (or text files holding a share),
+/// e.g. all those in a folder the user picked.
+#[tauri::command]
+async fn import_shares_from_files(paths: Vec<String>) -> Result<Vec<String>, AppError> {
+    println!("Importing {} share files", paths.len());
+    
+    file_ops::read_share_files(&paths)
+}
+
+/// Writes each share as words for a custodian to copy onto paper, prefixed
+/// with the share's position and counts.
+#[tauri::command]
+async fn export_shares_as_mnemonics(shares: Vec<String>) -> Result<Vec<String>, AppError> {
+    println!("Exporting {} shares as mnemonics", shares.len());
+    
+    Ok(sss::shares_to_mnemonics(&shares)?)
+}
+
+/// Reads shares back from the lines written by `export_shares_as_mnemonics`.
+#[tauri::command]
+async fn import_shares_from_mnemonics(mnemonics: Vec<String>) -> Result<Vec<String>, AppError> {
+    println!("Importing {} mnemonic shares", mnemonics.len());
+    
+    Ok(sss::shares_from_mnemonics(&mnemonics)?)
+}
+
+/// Reconstructs the secret of `shares` without decrypting anything, e.g. to
+/// back up a file's key. Resolves to the raw key material base64-encoded:
+/// whoever sees it can decrypt the file without any shares, so it must be
+/// handled like the shares themselves.
+#[tauri::command]
+async fn combine_shares(shares: Vec<String>) -> Result<String, AppError> {
+    println!("Combining {} shares", shares.len());
+    
+    Ok(sss::combine_shares(&shares)?.to_string())
+}
+
+/// Like `verify_file`, but resolves to `false` when the file is damaged or
+/// has been tampered with, for scripts that check backups periodically.
+#[tauri::command]
+async fn verify_file_integrity(file_path: String, shares: Vec<String>, context: Option<String>) -> Result<bool, AppError> {
+    println!("Checking integrity of file: {} with {} shares", file_path, shares.len());
+    
+    file_ops::verify_file_integrity(&file_path, &shares, context.as_deref())
+}
+
+/// Encrypts text, e.g. clipboard contents or a form field, entirely in memory.
+#[tauri::command]
+async fn encrypt_text(plaintext: String, k: u8, n: u8) -> Result<TextEncryptionResult, AppError> {
+    let plaintext = Zeroizing::new(plaintext);
+    println!("Encrypting text with {}-of-{} sharing", k, n);
+    
+    file_ops::encrypt_text(&plaintext, k, n)
+}
+
+/// Decrypts text from [`encrypt_text`] without writing anything to disk.
+#[tauri::command]
+async fn decrypt_to_text(ciphertext_b64: String, shares: Vec<String>) -> Result<String, AppError> {
+    println!("Decrypting text with {} shares", shares.len());
+    
+    file_ops::decrypt_to_text(&ciphertext_b64, &shares).map(|text| text.to_string())
+}
+
+/// Decrypts a file without writing it anywhere, e.g. to preview an image.
+/// Resolves to the plaintext base64-encoded.
+#[tauri::command]
+async fn decrypt_file_to_bytes(file_path: String, shares: Vec<String>, context: Option<String>) -> Result<String, AppError> {
+    println!("Decrypting file: {} into memory with {} shares", file_path, shares.len());
+    
+    let bytes = file_ops::decrypt_file_to_bytes(&file_path, &shares, context.as_deref())?;
+    Ok(general_purpose::STANDARD.encode(&bytes))
+}
+
+/// Decrypts `length` bytes from `offset` of a file encrypted in chunks.
+/// Resolves to them base64-encoded, or writes them to `output_path` and
+/// resolves to nothing.
+#[tauri::command]
+async fn decrypt_range(
+    file_path: String,
+    shares: Vec<String>,
+    offset: u64,
+    length: u64,
+    context: Option<String>,
+    output_path: Option<String>,
+    overwrite: Option<bool>,
+) -> Result<Option<String>, AppError> {
+    println!("Decrypting {} bytes at {} of file: {} with {} shares", length, offset, file_path, shares.len());
+    
+    let bytes = file_ops::decrypt_range(&file_path, &shares, offset, length, context.as_deref())?;
+    match output_path {
+        Some(output_path) => {
+            file_ops::write_output(Path::new(&output_path), &bytes, overwrite.unwrap_or(false))?;
+            Ok(None)
+        }
+        None => Ok(Some(general_purpose::STANDARD.encode(&bytes))),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+#[tauri::command]
+async fn decrypt_file_with_password(
+    app: AppHandle,
+    file_path: String,
+    output_dir: String,
+    password: String,
+    context: Option<String>,
+    output_name: Option<String>,
+    overwrite: Option<bool>,
+    checksum_file: Option<bool>,
+) -> Result<DecryptionResult, AppError> {
+    println!("Decrypting file: {} to directory: {} with a password", file_path, output_dir);
+    
+    let password = Zeroizing::new(password);
+    let options = DecryptOptions {
+        context,
+        output_name,
+        overwrite: overwrite.unwrap_or(false),
+        checksum_file: checksum_file.unwrap_or(false),
+    };
+    run_blocking(move || {
+        file_ops::decrypt_file_with_password(
+            &file_path,
+            &output_dir,
+            &password,
+            &options,
+            &mut emit_progress(&app, DECRYPT_PROGRESS_EVENT),
+        )
+    })
+    .await
+}
+
+#[tauri::command]
+async fn decrypt_directory(
+    dir_path: String,
+    output_dir: String,
+    shares: Vec<String>,
+    overwrite: Option<bool>,
+) -> Result<Vec<DecryptionResult>, AppError> {
+    println!("Decrypting directory: {} to directory: {} with {} shares", dir_path, output_dir, shares.len());
+    
+    file_ops::decrypt_directory(&dir_path, &output_dir, &shares, overwrite.unwrap_or(false))
+}
+
+#[tauri::command]
+async fn decrypt_directory_archive(
+    file_path: String,
+    output_dir: String,
+    shares: Vec<String>,
+    overwrite: Option<bool>,
+) -> Result<Vec<DecryptionResult>, AppError> {
+    println!("Decrypting directory archive: {} to directory: {} with {} shares", file_path, output_dir, shares.len());
+    
+    file_ops::decrypt_directory_archive(&file_path, &output_dir, &shares, overwrite.unwrap_or(false))
+}
+
+#[tauri::command]
+async fn inspect_file(file_path: String) -> Result<FileInfo, AppError> {
+    file_ops::inspect_file(&file_path)
+}
+
+/// What [`inspect_cryptit_file`] reports about a `.cryptit` file, for a file
+/// manager to show next to it. None of it is secret.
+#[derive(Debug, Serialize)]
+pub struct FileMetadata {
+    pub version: u8,
+    pub cipher_algorithm: String,
+    /// Key derivation function of a password-protected file, e.g. "argon2id"
+    pub kdf: Option<String>,
+    /// Extension of the encrypted file, without the dot, when recorded
+    pub original_extension: Option<String>,
+    /// Size of the encrypted file on disk in bytes
+    pub encrypted_size: u64,
+    /// When the file was encrypted, in seconds since the Unix epoch
+    pub created_at: Option<u64>,
+}
+
+/// Reads the metadata in the header of a `.cryptit` file. No key is needed
+/// and nothing is decrypted.
+#[tauri::command]
+async fn inspect_cryptit_file(file_path: String) -> Result<FileMetadata, AppError> {
+    let info = file_ops::inspect_file(&file_path)?;
+    Ok(FileMetadata {
+        version: info.version,
+        cipher_algorithm: info.cipher,
+        kdf: info.kdf,
+        original_extension: info.original_extension,
+        encrypted_size: info.encrypted_size,
+        created_at: info.created_at,
     })
 }
 
+/// Restores a damaged header from the backup copy at the end of the file.
+/// Resolves to whether the file needed repairing.
+#[tauri::command]
+async fn repair_file(file_path: String) -> Result<bool, AppError> {
+    println!("Repairing file: {}", file_path);
+    
+    file_ops::repair_file(&file_path)
+}
+
+/// Writes each share as a QR code image in `output_dir`, resolving to the
+/// paths written.
+#[tauri::command]
+async fn shares_to_qr(shares: Vec<String>, output_dir: String) -> Result<Vec<String>, AppError> {
+    println!("Writing {} shares as QR codes to directory: {}", shares.len(), output_dir);
+    
+    qr::shares_to_qr(&shares, Path::new(&output_dir))
+}
+
+/// Runs file I/O and crypto on the blocking thread pool, so a large file
+/// doesn't hold up the async runtime that other commands are waiting on.
+async fn run_blocking<T, F>(work: F) -> Result<T, AppError>
+where
+    F: FnOnce() -> Result<T, AppError> + Send + 'static,
+    T: Send + 'static,
+{
+    tauri::async_runtime::spawn_blocking(work)
+        .await
+        .map_err(|e| AppError::Io(format!("Background task failed: {}", e)))?
+}
+
+/// Forwards progress to the frontend as `event`. Failing to emit only loses
+/// an update, so errors are ignored.
+fn emit_progress<'a>(app: &'a AppHandle, event: &'a str) -> impl FnMut(ProgressPayload) + 'a {
+    move |payload| {
+        let _ = app.emit(event, payload);
+    }
+}
+
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
     tauri::Builder::default()
         .plugin(tauri_plugin_opener::init())
         .plugin(tauri_plugin_dialog::init())
-        .invoke_handler(tauri::generate_handler![encrypt_file, decrypt_file])
+        .invoke_handler(tauri::generate_handler![
+            encrypt_file,
+            encrypt_file_with_password,
+            calibrate_kdf,
+            generate_keypair,
+            generate_signing_key,
+            encrypt_files,
+            encrypt_files_shared_key,
+            encrypt_directory,
+            encrypt_directory_archive,
+            decrypt_file,
+            decrypt_file_from_share_files,
+            verify_file,
+            export_shares_as_files,
+            import_shares_from_files,
+            export_shares_as_mnemonics,
+            import_shares_from_mnemonics,
+            combine_shares,
+            reshare_key,
+            verify_file_integrity,
+            decrypt_file_to_bytes,
+            encrypt_text,
+            decrypt_to_text,
+            decrypt_range,
+            decrypt_file_with_password,
+            decrypt_directory,
+            decrypt_directory_archive,
+            inspect_file,
+            inspect_cryptit_file,
+            repair_file,
+            shares_to_qr
+        ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_blocking_work_leaves_runtime_free() {
+        let workers = thread::available_parallelism().map_or(4, usize::from);
+        tauri::async_runtime::block_on(async {
+            // Stand-ins for large encryptions, more of them than the runtime
+            // has worker threads; run on the workers, they would stall them all
+            let jobs: Vec<_> = (0..workers * 2)
+                .map(|_| {
+                    tauri::async_runtime::spawn(run_blocking(|| {
+                        thread::sleep(Duration::from_millis(500));
+                        Ok(())
+                    }))
+                })
+                .collect();
+            thread::sleep(Duration::from_millis(50));
+
+            // A concurrent command still gets a worker straight away
+            let started = Instant::now();
+            tauri::async_runtime::spawn(async {}).await.unwrap();
+            assert!(started.elapsed() < Duration::from_millis(250), "waited {:?}", started.elapsed());
+
+            for job in jobs {
+                job.await.unwrap().unwrap();
+            }
+        });
+    }
+
+    #[test]
+    fn test_inspect_cryptit_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("notes.txt");
+        std::fs::write(&input, b"notes").unwrap();
+        let options = EncryptOptions::default();
+        let result = file_ops::encrypt_file_with_password(
+            input.to_str().unwrap(),
+            dir.path().to_str().unwrap(),
+            "password",
+            PasswordKdf::Pbkdf2,
+            &options,
+            &mut |_| {},
+        )
+        .unwrap();
+
+        let metadata = tauri::async_runtime::block_on(inspect_cryptit_file(result.encrypted_file_path.clone())).unwrap();
+        assert_eq!(metadata.version, format::FORMAT_VERSION);
+        assert_eq!(metadata.cipher_algorithm, CipherAlgorithm::default().name());
+        assert_eq!(metadata.kdf.as_deref(), Some("pbkdf2-sha256"));
+        assert_eq!(metadata.original_extension.as_deref(), Some("txt"));
+        assert_eq!(metadata.encrypted_size, std::fs::metadata(&result.encrypted_file_path).unwrap().len());
+        assert!(metadata.created_at.is_some());
+    }
+}
@@ -0,0 +1,87 @@
+//! Content type detection from the leading bytes of a file, for naming
+//! decrypted output when the original name wasn't recorded.
+
+/// Bytes of a file [`sniff`] looks at. The tar signature is the furthest in.
+pub const SNIFF_LEN: usize = 512;
+
+/// Known signatures: offset of the magic bytes, the bytes, the MIME type and
+/// the extension to give a file of that type.
+const SIGNATURES: &[(usize, &[u8], &str, &str)] = &[
+    (0, b"\x89PNG\r\n\x1a\n", "image/png", "png"),
+    (0, b"\xff\xd8\xff", "image/jpeg", "jpg"),
+    (0, b"GIF87a", "image/gif", "gif"),
+    (0, b"GIF89a", "image/gif", "gif"),
+    (0, b"%PDF-", "application/pdf", "pdf"),
+    (0, b"PK\x03\x04", "application/zip", "zip"),
+    (0, b"\x1f\x8b", "application/gzip", "gz"),
+    (0, b"BZh", "application/x-bzip2", "bz2"),
+    (0, b"\xfd7zXZ\x00", "application/x-xz", "xz"),
+    (0, b"\x28\xb5\x2f\xfd", "application/zstd", "zst"),
+    (0, b"7z\xbc\xaf\x27\x1c", "application/x-7z-compressed", "7z"),
+    (0, b"SQLite format 3\x00", "application/vnd.sqlite3", "sqlite"),
+    (0, b"ID3", "audio/mpeg", "mp3"),
+    (0, b"OggS", "audio/ogg", "ogg"),
+    (0, b"fLaC", "audio/flac", "flac"),
+    (4, b"ftyp", "video/mp4", "mp4"),
+    (257, b"ustar", "application/x-tar", "tar"),
+];
+
+/// MIME type of `data`, judged by its leading bytes alone.
+pub fn sniff(data: &[u8]) -> Option<&'static str> {
+    // RIFF is a container; the format is named by the four bytes at 8
+    if data.len() >= 12 && &data[..4] == b"RIFF" {
+        return match &data[8..12] {
+            b"WEBP" => Some("image/webp"),
+            b"WAVE" => Some("audio/wav"),
+            b"AVI " => Some("video/x-msvideo"),
+            _ => None,
+        };
+    }
+    SIGNATURES
+        .iter()
+        .find(|(offset, magic, _, _)| data.get(*offset..offset + magic.len()) == Some(*magic))
+        .map(|&(_, _, mime, _)| mime)
+}
+
+/// Extension, without the dot, for files of the MIME type `mime`.
+pub fn extension_for(mime: &str) -> Option<&'static str> {
+    match mime {
+        "image/webp" => Some("webp"),
+        "audio/wav" => Some("wav"),
+        "video/x-msvideo" => Some("avi"),
+        _ => SIGNATURES
+            .iter()
+            .find(|&&(_, _, known, _)| known == mime)
+            .map(|&(_, _, _, extension)| extension),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sniff() {
+        assert_eq!(sniff(b"\x89PNG\r\n\x1a\n\x00\x00\x00\rIHDR"), Some("image/png"));
+        assert_eq!(sniff(b"%PDF-1.7\n"), Some("application/pdf"));
+        assert_eq!(sniff(b"RIFF\x00\x00\x00\x00WEBPVP8 "), Some("image/webp"));
+        assert_eq!(sniff(b"\x00\x00\x00\x18ftypmp42"), Some("video/mp4"));
+
+        let mut tar = vec![0u8; SNIFF_LEN];
+        tar[257..262].copy_from_slice(b"ustar");
+        assert_eq!(sniff(&tar), Some("application/x-tar"));
+
+        // Too short to hold the whole signature, or plain text
+        assert_eq!(sniff(b"\x89PN"), None);
+        assert_eq!(sniff(b"hello world"), None);
+        assert_eq!(sniff(b""), None);
+    }
+
+    #[test]
+    fn test_extension_for() {
+        assert_eq!(extension_for("application/pdf"), Some("pdf"));
+        assert_eq!(extension_for("image/gif"), Some("gif"));
+        assert_eq!(extension_for("image/webp"), Some("webp"));
+        assert_eq!(extension_for("text/plain"), None);
+    }
+}
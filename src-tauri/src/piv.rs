@@ -0,0 +1,74 @@
+//! Reads the secret of a PIV-protected file from a smartcard such as a
+//! YubiKey. The secret is a data object on the card, read after the PIN is
+//! verified, and is hashed like a keyfile to derive the file key.
+//!
+//! Talking to a real card needs the `piv` feature; without it [`open_card`]
+//! reports that support wasn't built in.
+
+use crate::error::AppError;
+use thiserror::Error;
+use zeroize::Zeroizing;
+
+/// Data object the secret is read from unless another is chosen: the
+/// certificate object of retired key management slot 20, which is rarely
+/// used for a certificate.
+pub const DEFAULT_OBJECT_ID: u32 = 0x5F_C120;
+
+#[derive(Error, Debug)]
+pub enum PivError {
+    #[error("No PIV smartcard found: {0}")]
+    NoCard(String),
+    #[error("Wrong PIN: {0} tries left before the card locks")]
+    WrongPin(u8),
+    #[error("The smartcard holds no secret in data object {0:#08x}")]
+    MissingSecret(u32),
+    #[error("Smartcard error: {0}")]
+    Card(String),
+    #[error("This build of CryptIt was made without smartcard support")]
+    Unsupported,
+}
+
+impl From<PivError> for AppError {
+    fn from(err: PivError) -> Self {
+        AppError::Crypto(err.to_string())
+    }
+}
+
+/// Somewhere the secret of a PIV-protected file can be read from: an unlocked
+/// card, or a stand-in for one in tests.
+pub trait PivSecretSource {
+    /// Contents of the data object `object_id`.
+    fn read_secret(&mut self, object_id: u32) -> Result<Zeroizing<Vec<u8>>, PivError>;
+}
+
+/// Opens the first PIV smartcard connected and unlocks it with `pin`.
+#[cfg(feature = "piv")]
+pub fn open_card(pin: &str) -> Result<Box<dyn PivSecretSource>, PivError> {
+    let mut yubikey = yubikey::YubiKey::open().map_err(|e| PivError::NoCard(e.to_string()))?;
+    yubikey.verify_pin(pin.as_bytes()).map_err(|e| match e {
+        yubikey::Error::WrongPin { tries } => PivError::WrongPin(tries),
+        e => PivError::Card(e.to_string()),
+    })?;
+    Ok(Box::new(YubiKeyCard(yubikey)))
+}
+
+/// Opens the first PIV smartcard connected and unlocks it with `pin`.
+#[cfg(not(feature = "piv"))]
+pub fn open_card(_pin: &str) -> Result<Box<dyn PivSecretSource>, PivError> {
+    Err(PivError::Unsupported)
+}
+
+#[cfg(feature = "piv")]
+struct YubiKeyCard(yubikey::YubiKey);
+
+#[cfg(feature = "piv")]
+impl PivSecretSource for YubiKeyCard {
+    fn read_secret(&mut self, object_id: u32) -> Result<Zeroizing<Vec<u8>>, PivError> {
+        match self.0.fetch_object(object_id) {
+            Ok(data) if data.is_empty() => Err(PivError::MissingSecret(object_id)),
+            Ok(data) => Ok(Zeroizing::new(data.to_vec())),
+            Err(yubikey::Error::NotFound) => Err(PivError::MissingSecret(object_id)),
+            Err(e) => Err(PivError::Card(e.to_string())),
+        }
+    }
+}
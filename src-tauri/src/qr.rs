@@ -0,0 +1,202 @@
+//! Renders key shares as QR codes, so they can be printed and scanned back
+//! in instead of typed.
+
+use crate::error::AppError;
+use crate::file_ops::{to_hex, write_output};
+use image::{ImageFormat, Luma};
+use qrcode::QrCode;
+use sha2::{Digest, Sha256};
+use std::io::Cursor;
+use std::path::Path;
+use thiserror::Error;
+
+/// Prefix of the payload of one part of a share too long for a single code.
+const PART_PREFIX: &str = "CQR1";
+
+/// Most characters of a share put in one code. At 4 bytes per character
+/// this stays within a version 40 code at the default error correction,
+/// and keeps codes for ordinary shares small enough to scan from paper.
+const MAX_PART_CHARS: usize = 500;
+
+/// Bytes of the share's SHA-256 that identify it in the header of each part.
+const SHARE_ID_LEN: usize = 4;
+
+/// Side of one QR module in the rendered images, in pixels.
+const MODULE_PIXELS: u32 = 8;
+
+#[derive(Error, Debug)]
+pub enum QrError {
+    #[error("Failed to encode QR code: {0}")]
+    Encode(#[from] qrcode::types::QrError),
+    #[error("Failed to write QR code: {0}")]
+    Io(String),
+    #[error("QR code parts are malformed, incomplete or from different shares")]
+    InvalidParts,
+}
+
+impl From<QrError> for AppError {
+    fn from(err: QrError) -> Self {
+        match err {
+            QrError::Io(_) => AppError::Io(err.to_string()),
+            _ => AppError::Sss(err.to_string()),
+        }
+    }
+}
+
+/// Payloads of the codes for `share`: the share itself when it fits in one
+/// code, otherwise `CQR1:{id}:{part}:{total}:{text}` for each part, numbered
+/// from 1. `id` is the start of the share's SHA-256 in hex, so parts of
+/// different shares aren't joined.
+pub fn qr_payloads(share: &str) -> Vec<String> {
+    let chars: Vec<char> = share.chars().collect();
+    if chars.len() <= MAX_PART_CHARS {
+        return vec![share.to_string()];
+    }
+    let id = share_id(share);
+    let parts: Vec<&[char]> = chars.chunks(MAX_PART_CHARS).collect();
+    parts
+        .iter()
+        .enumerate()
+        .map(|(i, part)| format!("{}:{}:{}:{}:{}", PART_PREFIX, id, i + 1, parts.len(), part.iter().collect::<String>()))
+        .collect()
+}
+
+fn share_id(share: &str) -> String {
+    to_hex(&Sha256::digest(share.as_bytes())[..SHARE_ID_LEN])
+}
+
+/// Reassembles a share from the payloads of its codes, scanned in any order.
+pub fn join_qr_payloads(payloads: &[String]) -> Result<String, QrError> {
+    if let [payload] = payloads {
+        if !payload.starts_with(PART_PREFIX) {
+            return Ok(payload.clone());
+        }
+    }
+    let mut parts = payloads.iter().map(|payload| parse_part(payload)).collect::<Result<Vec<_>, _>>()?;
+    parts.sort_by_key(|part| part.index);
+    let total = parts.len();
+    let complete = parts
+        .iter()
+        .enumerate()
+        .all(|(i, part)| part.index == i + 1 && part.total == total && part.id == parts[0].id);
+    if !complete {
+        return Err(QrError::InvalidParts);
+    }
+    let share: String = parts.iter().map(|part| part.text).collect();
+    // Catches parts of two shares that happen to share an ID, or a part
+    // whose text was misread
+    if share_id(&share) != parts[0].id {
+        return Err(QrError::InvalidParts);
+    }
+    Ok(share)
+}
+
+/// One part of a share split across several codes by [`qr_payloads`].
+struct Part<'a> {
+    id: &'a str,
+    index: usize,
+    total: usize,
+    text: &'a str,
+}
+
+fn parse_part(payload: &str) -> Result<Part<'_>, QrError> {
+    let mut fields = payload.splitn(5, ':');
+    if fields.next() != Some(PART_PREFIX) {
+        return Err(QrError::InvalidParts);
+    }
+    let id = fields.next().ok_or(QrError::InvalidParts)?;
+    let mut number = || fields.next().and_then(|field| field.parse::<usize>().ok()).ok_or(QrError::InvalidParts);
+    let index = number()?;
+    let total = number()?;
+    let text = fields.next().ok_or(QrError::InvalidParts)?;
+    Ok(Part { id, index, total, text })
+}
+
+/// Writes each share as `share_{n}.png` in `output_dir`, numbering shares
+/// from 1. A share split across several codes is written as
+/// `share_{n}_part_{m}.png` instead. Returns the paths written, in order.
+/// Existing files are not replaced.
+pub fn shares_to_qr(shares: &[String], output_dir: &Path) -> Result<Vec<String>, AppError> {
+    let mut paths = Vec::new();
+    for (i, share) in shares.iter().enumerate() {
+        let payloads = qr_payloads(share.trim());
+        for (j, payload) in payloads.iter().enumerate() {
+            let file_name = if payloads.len() == 1 {
+                format!("share_{}.png", i + 1)
+            } else {
+                format!("share_{}_part_{}.png", i + 1, j + 1)
+            };
+            let path = output_dir.join(file_name);
+            let mut png = Vec::new();
+            render(payload)?
+                .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+                .map_err(|e| QrError::Io(format!("{}: {}", path.display(), e)))?;
+            write_output(&path, &png, false)?;
+            paths.push(path.to_string_lossy().to_string());
+        }
+    }
+    Ok(paths)
+}
+
+fn render(payload: &str) -> Result<image::GrayImage, QrError> {
+    let code = QrCode::new(payload.as_bytes())?;
+    Ok(code
+        .render::<Luma<u8>>()
+        .module_dimensions(MODULE_PIXELS, MODULE_PIXELS)
+        .build())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Scans the code in an image written by [`shares_to_qr`].
+    fn scan(path: &str) -> String {
+        let image = image::open(path).unwrap().to_luma8();
+        let grids = rqrr::PreparedImage::prepare(image).detect_grids();
+        assert_eq!(grids.len(), 1);
+        grids[0].decode().unwrap().1
+    }
+
+    #[test]
+    fn test_share_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let shares = vec!["CSHR1:1:2:3::c2hhcmUgb25l".to_string(), "CSHR1:2:2:3::c2hhcmUgdHdv".to_string()];
+        let paths = shares_to_qr(&shares, dir.path()).unwrap();
+        assert_eq!(paths.len(), 2);
+        assert!(paths[1].ends_with("share_2.png"));
+        for (path, share) in paths.iter().zip(&shares) {
+            assert_eq!(&join_qr_payloads(&[scan(path)]).unwrap(), share);
+        }
+
+        // Codes already written are left alone
+        let err = shares_to_qr(&shares[1..], dir.path()).unwrap_err();
+        assert!(matches!(err, AppError::OutputExists(_)), "unexpected error: {}", err);
+        assert_eq!(scan(&paths[0]), shares[0]);
+    }
+
+    #[test]
+    fn test_long_share_is_split() {
+        let dir = tempfile::tempdir().unwrap();
+        let share = format!("CSHR1:1:2:3:{}:c2hhcmU=", "é".repeat(MAX_PART_CHARS * 2));
+        let payloads = qr_payloads(&share);
+        assert_eq!(payloads.len(), 3);
+        assert!(payloads[0].starts_with(&format!("CQR1:{}:1:3:", share_id(&share))));
+
+        let paths = shares_to_qr(std::slice::from_ref(&share), dir.path()).unwrap();
+        assert!(paths[2].ends_with("share_1_part_3.png"));
+
+        // Scanned out of order
+        let scanned: Vec<String> = paths.iter().rev().map(|path| scan(path)).collect();
+        assert_eq!(join_qr_payloads(&scanned).unwrap(), share);
+
+        assert!(matches!(join_qr_payloads(&payloads[..2]), Err(QrError::InvalidParts)));
+        let duplicated = vec![payloads[0].clone(), payloads[0].clone(), payloads[2].clone()];
+        assert!(matches!(join_qr_payloads(&duplicated), Err(QrError::InvalidParts)));
+
+        // A part of another share of the same length doesn't fit in
+        let other = qr_payloads(&share.replacen("CSHR1:1", "CSHR1:2", 1));
+        let mixed = vec![other[0].clone(), payloads[1].clone(), payloads[2].clone()];
+        assert!(matches!(join_qr_payloads(&mixed), Err(QrError::InvalidParts)));
+    }
+}
@@ -1,10 +1,36 @@
 use shamirs::{combine, split};
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 use base64::{Engine, engine::general_purpose};
+use bip39::{Language, Mnemonic};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::collections::HashSet;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use zeroize::Zeroizing;
+
+/// Prefix of the share strings handed to custodians. Bare base64 shares
+/// written by earlier releases have no prefix and no metadata.
+const SHARE_PREFIX: &str = "CSHR1";
+
+/// Prefix of shares bound to a file, which carry the file's ID after the
+/// share counts.
+const FILE_SHARE_PREFIX: &str = "CSHR2";
+
+/// Magic bytes that open a `.share` file.
+pub const SHARE_FILE_MAGIC: &[u8; 4] = b"CSHF";
+
+const SHARE_FILE_VERSION: u8 = 1;
+
+/// Length of the HMAC-SHA256 appended to the raw bytes of shares that carry
+/// metadata.
+pub const SHARE_MAC_LEN: usize = 32;
 
 #[derive(Error, Debug)]
 pub enum SSSError {
-    #[error("Invalid threshold: k must be <= n and both must be > 0")]
+    #[error("Invalid threshold: k must be at least 2 and at most n")]
     InvalidThreshold,
     #[error("Failed to generate shares")]
     ShareGenerationFailed,
@@ -14,53 +40,652 @@ pub enum SSSError {
     InvalidShareFormat,
     #[error("Insufficient shares provided")]
     InsufficientShares,
+    #[error("Shares disagree on the threshold or total share count; they are not from the same split")]
+    InconsistentMetadata,
+    #[error("Expected {expected} share labels, got {actual}")]
+    LabelCountMismatch { expected: u8, actual: usize },
+    #[error("Invalid share label: {0:?}")]
+    InvalidLabel(String),
+    #[error("Share {index} is corrupted; ask its custodian for a fresh copy")]
+    CorruptedShare { index: u8 },
+    /// `index` is the share's position in the split, or for bare shares from
+    /// earlier releases, which don't know it, the order it was entered in.
+    #[error("Share {index} was entered more than once")]
+    DuplicateShare { index: u8 },
+    /// `position` counts the shares in the order they were entered, from 1.
+    #[error("Share {position} belongs to a different file")]
+    WrongFile { position: usize },
+    #[error("Invalid mnemonic: {0}")]
+    InvalidMnemonic(#[from] bip39::Error),
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+}
+
+/// One share together with what a custodian needs to know about it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LabeledShare {
+    /// Position of the share in the split, from 1 to `total`
+    pub index: u8,
+    /// Who the share is meant for
+    pub label: String,
+    /// Shares needed to reconstruct the secret (k)
+    pub threshold: u8,
+    /// Shares that were issued (n)
+    pub total: u8,
+    /// The share itself, base64 encoded
+    pub data: String,
+    /// ID of the file whose key was split (see
+    /// [`crate::format::FileHeader::file_id`]). Absent for shares of a bare
+    /// key and for shares written before files had IDs.
+    pub file_id: Option<String>,
+}
+
+impl LabeledShare {
+    /// Single-line form: `CSHR1:{index}:{threshold}:{total}:{label}:{data}`,
+    /// or `CSHR2:{index}:{threshold}:{total}:{file_id}:{label}:{data}` for a
+    /// share bound to a file.
+    pub fn encode(&self) -> String {
+        match &self.file_id {
+            Some(file_id) => format!(
+                "{}:{}:{}:{}:{}:{}:{}",
+                FILE_SHARE_PREFIX, self.index, self.threshold, self.total, file_id, self.label, self.data
+            ),
+            None => format!(
+                "{}:{}:{}:{}:{}:{}",
+                SHARE_PREFIX, self.index, self.threshold, self.total, self.label, self.data
+            ),
+        }
+    }
+
+    /// Parses [`LabeledShare::encode`] output. A bare base64 share from an
+    /// earlier release is accepted with `index`, `threshold` and `total` of 0,
+    /// meaning unknown.
+    pub fn decode(encoded: &str) -> Result<Self, SSSError> {
+        let encoded = encoded.trim();
+        let prefixed = |prefix: &str| encoded.strip_prefix(prefix).and_then(|rest| rest.strip_prefix(':'));
+        let (rest, has_file_id) = match (prefixed(SHARE_PREFIX), prefixed(FILE_SHARE_PREFIX)) {
+            (Some(rest), _) => (rest, false),
+            (_, Some(rest)) => (rest, true),
+            _ => {
+                return Ok(Self {
+                    index: 0,
+                    label: String::new(),
+                    threshold: 0,
+                    total: 0,
+                    data: encoded.to_string(),
+                    file_id: None,
+                })
+            }
+        };
+
+        // The label may itself contain ':', but base64 data never does
+        let mut fields = rest.splitn(if has_file_id { 5 } else { 4 }, ':');
+        let mut number = || {
+            fields
+                .next()
+                .and_then(|field| field.parse::<u8>().ok())
+                .ok_or(SSSError::InvalidShareFormat)
+        };
+        let (index, threshold, total) = (number()?, number()?, number()?);
+        let file_id = match has_file_id {
+            true => Some(fields.next().filter(|id| !id.is_empty()).ok_or(SSSError::InvalidShareFormat)?),
+            false => None,
+        };
+        let (label, data) = fields
+            .next()
+            .and_then(|rest| rest.rsplit_once(':'))
+            .ok_or(SSSError::InvalidShareFormat)?;
+
+        Ok(Self {
+            index,
+            label: label.to_string(),
+            threshold,
+            total,
+            data: data.to_string(),
+            file_id: file_id.map(str::to_string),
+        })
+    }
+
+    fn has_metadata(&self) -> bool {
+        self.threshold != 0
+    }
 }
 
-pub fn split_secret(secret: &[u8], k: u8, n: u8) -> Result<Vec<String>, SSSError> {
-    if k == 0 || n == 0 || k > n {
+/// Decodes share strings as entered by the user, one per share.
+pub fn decode_shares(encoded_shares: &[String]) -> Result<Vec<LabeledShare>, SSSError> {
+    encoded_shares
+        .iter()
+        .map(|encoded| LabeledShare::decode(encoded))
+        .collect()
+}
+
+/// Checks that `k` of `n` shares is a valid split, so callers can reject a
+/// bad threshold before doing any work. A single share can't be split off
+/// from the secret, so `k` has to be at least 2.
+pub fn check_threshold(k: u8, n: u8) -> Result<(), SSSError> {
+    if k < 2 || k > n {
         return Err(SSSError::InvalidThreshold);
     }
+    Ok(())
+}
 
-    // Use the shamirs crate - much simpler API!
-    let shares = split(secret, n as usize, k as usize)
-        .map_err(|_| SSSError::ShareGenerationFailed)?;
-    
-    // Encode shares as base64 strings for easy transport
-    let encoded_shares: Vec<String> = shares
+/// Writes `share` to `path` in the binary `.share` format:
+/// `[magic "CSHF"][version][index][threshold][total][label_len u16 LE][label]
+/// [file_id_len][file_id][data_len u16 LE][data]`, with the share data raw
+/// rather than base64 encoded. Fails if `path` already exists.
+pub fn write_share_file(share: &LabeledShare, path: &Path) -> Result<(), SSSError> {
+    let data = Zeroizing::new(
+        general_purpose::STANDARD
+            .decode(&share.data)
+            .map_err(|_| SSSError::InvalidShareFormat)?,
+    );
+    let label = share.label.as_bytes();
+    let file_id = share.file_id.as_deref().unwrap_or_default().as_bytes();
+    let (Ok(label_len), Ok(file_id_len), Ok(data_len)) =
+        (u16::try_from(label.len()), u8::try_from(file_id.len()), u16::try_from(data.len()))
+    else {
+        return Err(SSSError::InvalidShareFormat);
+    };
+
+    let mut bytes = Zeroizing::new(SHARE_FILE_MAGIC.to_vec());
+    bytes.extend_from_slice(&[SHARE_FILE_VERSION, share.index, share.threshold, share.total]);
+    bytes.extend_from_slice(&label_len.to_le_bytes());
+    bytes.extend_from_slice(label);
+    bytes.push(file_id_len);
+    bytes.extend_from_slice(file_id);
+    bytes.extend_from_slice(&data_len.to_le_bytes());
+    bytes.extend_from_slice(&data);
+
+    let mut file = OpenOptions::new().write(true).create_new(true).open(path)?;
+    file.write_all(&bytes)?;
+    file.sync_all()?;
+    Ok(())
+}
+
+/// Reads a share written by [`write_share_file`].
+pub fn read_share_file(path: &Path) -> Result<LabeledShare, SSSError> {
+    decode_share_file(&Zeroizing::new(fs::read(path)?))
+}
+
+/// Parses the contents of a `.share` file.
+pub fn decode_share_file(bytes: &[u8]) -> Result<LabeledShare, SSSError> {
+    let mut rest = bytes.strip_prefix(SHARE_FILE_MAGIC).ok_or(SSSError::InvalidShareFormat)?;
+    let fixed = take(&mut rest, 4)?;
+    let (version, index, threshold, total) = (fixed[0], fixed[1], fixed[2], fixed[3]);
+    if version != SHARE_FILE_VERSION {
+        return Err(SSSError::InvalidShareFormat);
+    }
+    let label_len = take(&mut rest, 2)?;
+    let label = take(&mut rest, u16::from_le_bytes([label_len[0], label_len[1]]) as usize)?;
+    let file_id_len = take(&mut rest, 1)?[0];
+    let file_id = take(&mut rest, file_id_len as usize)?;
+    let data_len = take(&mut rest, 2)?;
+    let data = take(&mut rest, u16::from_le_bytes([data_len[0], data_len[1]]) as usize)?;
+    if !rest.is_empty() {
+        return Err(SSSError::InvalidShareFormat);
+    }
+
+    let text = |bytes: &[u8]| String::from_utf8(bytes.to_vec()).map_err(|_| SSSError::InvalidShareFormat);
+    Ok(LabeledShare {
+        index,
+        label: text(label)?,
+        threshold,
+        total,
+        data: general_purpose::STANDARD.encode(data),
+        file_id: if file_id.is_empty() { None } else { Some(text(file_id)?) },
+    })
+}
+
+/// Splits the first `len` bytes off `rest`.
+fn take<'a>(rest: &mut &'a [u8], len: usize) -> Result<&'a [u8], SSSError> {
+    if rest.len() < len {
+        return Err(SSSError::InvalidShareFormat);
+    }
+    let (taken, remaining) = rest.split_at(len);
+    *rest = remaining;
+    Ok(taken)
+}
+
+/// Writes the share's bytes as words a custodian can copy onto paper: a
+/// BIP39 phrase (24 words for a 256-bit key) for the y-values, then one more
+/// word from the same list for the x-coordinate. The MAC is left out and
+/// recomputed by [`mnemonic_to_share`], so is the file ID. Bare shares from
+/// earlier releases don't know their position and can't be written this way.
+pub fn share_to_mnemonic(share: &LabeledShare) -> Result<String, SSSError> {
+    if !share.has_metadata() {
+        return Err(SSSError::InvalidShareFormat);
+    }
+    if !verify_share_integrity(share, &dealer_hmac_key(share)) {
+        return Err(SSSError::CorruptedShare { index: share.index });
+    }
+    let raw = Zeroizing::new(
+        general_purpose::STANDARD
+            .decode(&share.data)
+            .map_err(|_| SSSError::InvalidShareFormat)?,
+    );
+    let raw = &raw[..raw.len() - SHARE_MAC_LEN];
+    let (&x, y) = raw.split_last().ok_or(SSSError::InvalidShareFormat)?;
+
+    let mnemonic = Mnemonic::from_entropy(y)?;
+    let mut words = Zeroizing::new(mnemonic.words().collect::<Vec<_>>().join(" "));
+    words.push(' ');
+    words.push_str(Language::English.word_list()[x as usize]);
+    Ok(words.to_string())
+}
+
+/// Turns the words written by [`share_to_mnemonic`] back into share `index`
+/// of a `threshold` of `total` split, labelled "Share {index}".
+pub fn mnemonic_to_share(mnemonic: &str, index: u8, threshold: u8, total: u8) -> Result<LabeledShare, SSSError> {
+    check_threshold(threshold, total)?;
+    if index == 0 || index > total {
+        return Err(SSSError::InvalidShareFormat);
+    }
+    let mnemonic = mnemonic.trim().to_lowercase();
+    let (phrase, x_word) = mnemonic.rsplit_once(char::is_whitespace).ok_or(SSSError::InvalidShareFormat)?;
+    let x = Language::English
+        .find_word(x_word)
+        .and_then(|x| u8::try_from(x).ok())
+        .ok_or(bip39::Error::UnknownWord(phrase.split_whitespace().count()))?;
+
+    let mut raw = Zeroizing::new(Mnemonic::parse_in(Language::English, phrase)?.to_entropy());
+    raw.push(x);
+    let mut share = LabeledShare {
+        index,
+        label: format!("Share {}", index),
+        threshold,
+        total,
+        data: String::new(),
+        file_id: None,
+    };
+    let mac = share_mac(&share, &raw, &dealer_hmac_key(&share));
+    raw.extend_from_slice(&mac);
+    share.data = general_purpose::STANDARD.encode(&raw);
+    Ok(share)
+}
+
+/// [`share_to_mnemonic`] for share strings as entered by the user, each
+/// prefixed with the counts `mnemonic_to_share` needs:
+/// `{index}:{threshold}:{total}: {words}`.
+pub fn shares_to_mnemonics(encoded_shares: &[String]) -> Result<Vec<String>, SSSError> {
+    decode_shares(encoded_shares)?
+        .iter()
+        .map(|share| {
+            let words = share_to_mnemonic(share)?;
+            Ok(format!("{}:{}:{}: {}", share.index, share.threshold, share.total, words))
+        })
+        .collect()
+}
+
+/// Reads lines written by [`shares_to_mnemonics`] back into share strings.
+pub fn shares_from_mnemonics(lines: &[String]) -> Result<Vec<String>, SSSError> {
+    lines
+        .iter()
+        .map(|line| {
+            let mut fields = line.trim().splitn(4, ':');
+            let mut number = || {
+                fields
+                    .next()
+                    .and_then(|field| field.trim().parse::<u8>().ok())
+                    .ok_or(SSSError::InvalidShareFormat)
+            };
+            let (index, threshold, total) = (number()?, number()?, number()?);
+            let words = fields.next().ok_or(SSSError::InvalidShareFormat)?;
+            Ok(mnemonic_to_share(words, index, threshold, total)?.encode())
+        })
+        .collect()
+}
+
+/// Splits `secret` into `n` shares, labelled "Share 1" to "Share n".
+pub fn split_secret(secret: &[u8], k: u8, n: u8) -> Result<Vec<LabeledShare>, SSSError> {
+    split_secret_labeled(secret, k, n, &[])
+}
+
+/// Like [`split_secret`], but labels each share with the matching entry of
+/// `labels` (e.g. custodian names). `labels` must be empty or hold `n` names.
+pub fn split_secret_labeled(
+    secret: &[u8],
+    k: u8,
+    n: u8,
+    labels: &[String],
+) -> Result<Vec<LabeledShare>, SSSError> {
+    split_labeled(secret, k, n, labels, None, shamirs_split)
+}
+
+/// Like [`split_secret_labeled`], but binds every share to the file with ID
+/// `file_id`, so [`check_file_id`] can tell its shares from another file's.
+pub fn split_secret_for_file(
+    secret: &[u8],
+    k: u8,
+    n: u8,
+    labels: &[String],
+    file_id: &str,
+) -> Result<Vec<LabeledShare>, SSSError> {
+    split_labeled(secret, k, n, labels, Some(file_id), shamirs_split)
+}
+
+fn split_labeled(
+    secret: &[u8],
+    k: u8,
+    n: u8,
+    labels: &[String],
+    file_id: Option<&str>,
+    split_raw: impl FnOnce(&[u8], usize, usize) -> Result<Zeroizing<Vec<Vec<u8>>>, SSSError>,
+) -> Result<Vec<LabeledShare>, SSSError> {
+    check_threshold(k, n)?;
+    if !labels.is_empty() && labels.len() != n as usize {
+        return Err(SSSError::LabelCountMismatch {
+            expected: n,
+            actual: labels.len(),
+        });
+    }
+    // Labels end up in a single-line share string
+    if let Some(label) = labels.iter().find(|label| label.chars().any(char::is_control)) {
+        return Err(SSSError::InvalidLabel(label.clone()));
+    }
+
+    let shares = split_raw(secret, n as usize, k as usize)?;
+
+    // Encode shares as base64 strings for easy transport, each followed by
+    // its MAC
+    let labeled_shares = shares
         .iter()
-        .map(|share| general_purpose::STANDARD.encode(share))
+        .zip(1..=n)
+        .map(|(share, index)| {
+            let mut labeled = LabeledShare {
+                index,
+                label: labels
+                    .get(index as usize - 1)
+                    .map(|label| label.trim().to_string())
+                    .unwrap_or_else(|| format!("Share {}", index)),
+                threshold: k,
+                total: n,
+                data: String::new(),
+                file_id: file_id.map(str::to_string),
+            };
+            let hmac_key = dealer_hmac_key(&labeled);
+            let mut raw = Zeroizing::new(share.clone());
+            raw.extend_from_slice(&share_mac(&labeled, share, &hmac_key));
+            labeled.data = general_purpose::STANDARD.encode(&raw);
+            labeled
+        })
         .collect();
 
-    Ok(encoded_shares)
+    Ok(labeled_shares)
 }
 
-pub fn reconstruct_secret(encoded_shares: &[String]) -> Result<Vec<u8>, SSSError> {
-    if encoded_shares.is_empty() {
+/// Raw shares from the shamirs crate, which draws its randomness from the
+/// thread RNG.
+fn shamirs_split(secret: &[u8], parts: usize, threshold: usize) -> Result<Zeroizing<Vec<Vec<u8>>>, SSSError> {
+    split(secret, parts, threshold)
+        .map(Zeroizing::new)
+        .map_err(|_| SSSError::ShareGenerationFailed)
+}
+
+/// Key for the share MACs. It is derived from the threshold and total, which
+/// every share of a split agrees on, so it can be recomputed from whichever
+/// share comes first and no extra key material has to be handed out. The MAC
+/// catches corrupted shares; it is not meant to stop a custodian forging one.
+pub fn dealer_hmac_key(share: &LabeledShare) -> [u8; 32] {
+    let mut mac =
+        <Hmac<Sha256> as Mac>::new_from_slice(b"CryptIt share MAC v1").expect("HMAC accepts any key length");
+    mac.update(&[share.threshold, share.total]);
+    mac.finalize().into_bytes().into()
+}
+
+/// Checks the HMAC-SHA256 at the end of `share`'s raw bytes. Bare shares
+/// from earlier releases have no MAC and never verify.
+pub fn verify_share_integrity(share: &LabeledShare, dealer_hmac_key: &[u8; 32]) -> bool {
+    let Ok(raw) = general_purpose::STANDARD.decode(&share.data) else {
+        return false;
+    };
+    let Some(split_at) = raw.len().checked_sub(SHARE_MAC_LEN) else {
+        return false;
+    };
+    let (raw, tag) = raw.split_at(split_at);
+    share_mac_state(share, raw, dealer_hmac_key).verify_slice(tag).is_ok()
+}
+
+/// MAC over the share's position, the split and file it belongs to and its
+/// bytes, so a share can't be renumbered or moved to another file without
+/// being detected.
+fn share_mac(share: &LabeledShare, raw: &[u8], hmac_key: &[u8; 32]) -> [u8; SHARE_MAC_LEN] {
+    share_mac_state(share, raw, hmac_key).finalize().into_bytes().into()
+}
+
+fn share_mac_state(share: &LabeledShare, raw: &[u8], hmac_key: &[u8; 32]) -> Hmac<Sha256> {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(hmac_key).expect("HMAC accepts any key length");
+    mac.update(&[share.index, share.threshold, share.total]);
+    if let Some(file_id) = &share.file_id {
+        mac.update(file_id.as_bytes());
+    }
+    mac.update(raw);
+    mac
+}
+
+/// Checks that every share that names a file names the one with ID
+/// `file_id`. Shares without an ID, from before files had one, are let
+/// through for the key check to judge.
+pub fn check_file_id(shares: &[LabeledShare], file_id: &str) -> Result<(), SSSError> {
+    match shares
+        .iter()
+        .position(|share| share.file_id.as_deref().is_some_and(|id| id != file_id))
+    {
+        Some(position) => Err(SSSError::WrongFile { position: position + 1 }),
+        None => Ok(()),
+    }
+}
+
+/// Decodes share strings and checks that they can be combined: shares that
+/// carry metadata must agree on `k` and `n`, and at least `k` must be given.
+pub fn validate_shares(encoded_shares: &[String]) -> Result<Vec<LabeledShare>, SSSError> {
+    let shares = decode_shares(encoded_shares)?;
+    check_share_set(&shares)?;
+    Ok(shares)
+}
+
+fn check_share_set(shares: &[LabeledShare]) -> Result<(), SSSError> {
+    if shares.is_empty() {
         return Err(SSSError::InsufficientShares);
     }
 
-    // Decode base64 shares
-    let shares: Result<Vec<Vec<u8>>, _> = encoded_shares
+    let mut with_metadata = shares.iter().filter(|share| share.has_metadata());
+    if let Some(first) = with_metadata.next() {
+        if with_metadata.any(|share| (share.threshold, share.total) != (first.threshold, first.total)) {
+            return Err(SSSError::InconsistentMetadata);
+        }
+        if shares.len() < first.threshold as usize {
+            return Err(SSSError::InsufficientShares);
+        }
+    }
+    Ok(())
+}
+
+/// Combines `shares` back into the secret after the same checks as
+/// [`validate_shares`], so too few shares fail instead of producing garbage.
+pub fn reconstruct_secret(shares: &[LabeledShare]) -> Result<Zeroizing<Vec<u8>>, SSSError> {
+    check_share_set(shares)?;
+
+    // Check every share's MAC before combining, so a corrupted share is
+    // reported by index rather than silently yielding a wrong secret
+    let mut with_metadata = shares.iter().filter(|share| share.has_metadata());
+    if let Some(first) = with_metadata.clone().next() {
+        let hmac_key = dealer_hmac_key(first);
+        if let Some(share) = with_metadata.find(|share| !verify_share_integrity(share, &hmac_key)) {
+            return Err(SSSError::CorruptedShare { index: share.index });
+        }
+    }
+
+    // Decode base64 shares, dropping the MAC from those that carry one
+    let decoded: Result<Vec<Vec<u8>>, SSSError> = shares
         .iter()
-        .map(|encoded_share| {
-            general_purpose::STANDARD
-                .decode(encoded_share)
-                .map_err(|_| SSSError::InvalidShareFormat)
+        .map(|share| {
+            let mut raw = general_purpose::STANDARD
+                .decode(&share.data)
+                .map_err(|_| SSSError::InvalidShareFormat)?;
+            if share.has_metadata() {
+                let len = raw.len().checked_sub(SHARE_MAC_LEN).ok_or(SSSError::InvalidShareFormat)?;
+                raw.truncate(len);
+            }
+            Ok(raw)
         })
         .collect();
     
-    let shares = shares?;
+    // Any k of these are as good as the secret
+    let decoded = Zeroizing::new(decoded?);
+
+    // Combining a share with itself yields a wrong secret rather than an
+    // error. The x-coordinate is the last byte of each share.
+    let mut x_coordinates = HashSet::new();
+    for (position, (share, raw)) in shares.iter().zip(decoded.iter()).enumerate() {
+        let x = raw.last().ok_or(SSSError::InvalidShareFormat)?;
+        if !x_coordinates.insert(*x) {
+            let index = if share.has_metadata() { share.index } else { (position + 1) as u8 };
+            return Err(SSSError::DuplicateShare { index });
+        }
+    }
     
     // Use the shamirs crate to reconstruct - super simple!
-    let secret = combine(&shares)
+    let secret = combine(&decoded)
         .map_err(|_| SSSError::ReconstructionFailed)?;
 
-    Ok(secret)
+    Ok(Zeroizing::new(secret))
+}
+
+/// Decodes `encoded_shares` and reconstructs their secret, returned
+/// base64-encoded without reference to any file. For a file's shares this is
+/// the raw file key, which decrypts the file without any of the shares.
+pub fn combine_shares(encoded_shares: &[String]) -> Result<Zeroizing<String>, SSSError> {
+    let secret = reconstruct_secret(&decode_shares(encoded_shares)?)?;
+    Ok(Zeroizing::new(general_purpose::STANDARD.encode(secret.as_slice())))
+}
+
+/// Splitting with a given RNG, for the golden fixture generator, which has to
+/// produce the same shares on every run. The shamirs crate only splits with
+/// the thread RNG, so this does its split over again; the tests check that
+/// the two agree.
+#[cfg(test)]
+pub(crate) mod seeded {
+    use super::*;
+    use rand::seq::SliceRandom;
+    use rand::{CryptoRng, RngCore};
+
+    /// [`split_secret_for_file`](super::split_secret_for_file) with the
+    /// randomness of the split drawn from `rng`.
+    pub(crate) fn split_secret_for_file(
+        secret: &[u8],
+        k: u8,
+        n: u8,
+        labels: &[String],
+        file_id: &str,
+        rng: &mut (impl RngCore + CryptoRng),
+    ) -> Result<Vec<LabeledShare>, SSSError> {
+        split_labeled(secret, k, n, labels, Some(file_id), |secret, parts, threshold| {
+            split(secret, parts, threshold, rng)
+        })
+    }
+
+    /// Splits `secret` into `parts` raw shares, any `threshold` of which
+    /// recover it, in the layout [`combine`] reads: one byte per byte of the
+    /// secret, each a point on its own random polynomial over GF(2^8), then
+    /// the x-coordinate of the share.
+    pub(super) fn split(
+        secret: &[u8],
+        parts: usize,
+        threshold: usize,
+        rng: &mut (impl RngCore + CryptoRng),
+    ) -> Result<Zeroizing<Vec<Vec<u8>>>, SSSError> {
+        // One share can't be interpolated by combine, and x = 0 is the secret
+        if secret.is_empty() || threshold < 2 || parts < threshold || parts > 255 {
+            return Err(SSSError::ShareGenerationFailed);
+        }
+        let mut xs: Vec<u8> = (1..=255).collect();
+        xs.shuffle(rng);
+
+        let mut shares = Zeroizing::new(vec![vec![0u8; secret.len() + 1]; parts]);
+        for (share, &x) in shares.iter_mut().zip(&xs) {
+            share[secret.len()] = x;
+        }
+        let mut coefficients = Zeroizing::new(vec![0u8; threshold]);
+        for (i, &byte) in secret.iter().enumerate() {
+            rng.fill_bytes(&mut coefficients[1..]);
+            coefficients[0] = byte;
+            for share in shares.iter_mut() {
+                let x = share[secret.len()];
+                share[i] = coefficients.iter().rev().fold(0, |y, &coefficient| gf_mul(y, x) ^ coefficient);
+            }
+        }
+        Ok(shares)
+    }
+
+    /// Product in GF(2^8) modulo x^8 + x^4 + x^3 + x + 1, the field
+    /// [`combine`] works in.
+    pub(super) fn gf_mul(mut a: u8, mut b: u8) -> u8 {
+        let mut product = 0u8;
+        for _ in 0..8 {
+            product ^= a & 0u8.wrapping_sub(b & 1);
+            a = (a << 1) ^ (0x1b & 0u8.wrapping_sub(a >> 7));
+            b >>= 1;
+        }
+        product
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_share_file_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let labels = ["Alice: ops".to_string(), "Bob".to_string(), "Carol".to_string()];
+        let shares = split_secret_for_file(b"secret", 2, 3, &labels, "file-1").unwrap();
+        let paths: Vec<_> = (1..=3).map(|i| dir.path().join(format!("share-{}.share", i))).collect();
+        for (share, path) in shares.iter().zip(&paths) {
+            write_share_file(share, path).unwrap();
+        }
+
+        let read: Vec<LabeledShare> = paths.iter().map(|path| read_share_file(path).unwrap()).collect();
+        assert_eq!(read, shares);
+        assert_eq!(reconstruct_secret(&read[1..]).unwrap().as_slice(), b"secret");
+
+        // Existing files are left alone
+        assert!(matches!(write_share_file(&shares[0], &paths[1]), Err(SSSError::Io(_))));
+        assert_eq!(read_share_file(&paths[1]).unwrap(), shares[1]);
+
+        // Truncated or padded files are rejected
+        let bytes = fs::read(&paths[0]).unwrap();
+        assert!(matches!(decode_share_file(&bytes[..bytes.len() - 1]), Err(SSSError::InvalidShareFormat)));
+        let mut padded = bytes.clone();
+        padded.push(0);
+        assert!(matches!(decode_share_file(&padded), Err(SSSError::InvalidShareFormat)));
+        assert!(matches!(decode_share_file(shares[0].encode().as_bytes()), Err(SSSError::InvalidShareFormat)));
+    }
+
+    #[test]
+    fn test_mnemonic_round_trip() {
+        let secret = [0x5a; 32];
+        let shares = split_secret_for_file(&secret, 2, 3, &[], "file-1").unwrap();
+        let words = share_to_mnemonic(&shares[1]).unwrap();
+        assert_eq!(words.split_whitespace().count(), 25);
+
+        // The file ID isn't written down, so the share comes back without it
+        let restored = mnemonic_to_share(&words.to_uppercase(), 2, 2, 3).unwrap();
+        assert_eq!(restored.file_id, None);
+        assert_eq!(reconstruct_secret(&[shares[0].clone(), restored]).unwrap().as_slice(), &secret);
+
+        // Skipped or unknown words are caught before the share is rebuilt
+        let skipped = words.split_once(' ').unwrap().1;
+        assert!(matches!(mnemonic_to_share(skipped, 2, 2, 3), Err(SSSError::InvalidMnemonic(_))));
+        let misspelt = format!("{}x", words);
+        assert!(matches!(mnemonic_to_share(&misspelt, 2, 2, 3), Err(SSSError::InvalidMnemonic(_))));
+        assert!(matches!(mnemonic_to_share(&words, 4, 2, 3), Err(SSSError::InvalidShareFormat)));
+
+        let lines = shares_to_mnemonics(&[shares[0].encode(), shares[2].encode()]).unwrap();
+        assert!(lines[0].starts_with("1:2:3: "));
+        let imported = decode_shares(&shares_from_mnemonics(&lines).unwrap()).unwrap();
+        assert_eq!(reconstruct_secret(&imported).unwrap().as_slice(), &secret);
+    }
 
     #[test]
     fn test_split_reconstruct() {
@@ -71,8 +696,9 @@ mod tests {
         let shares = split_secret(secret, k, n).unwrap();
         assert_eq!(shares.len(), n as usize);
         
-        // Test with minimum shares
-        let reconstructed = reconstruct_secret(&shares[0..k as usize]).unwrap();
+        // Test with minimum shares. The secret comes back wrapped so that it
+        // is wiped when dropped.
+        let reconstructed: Zeroizing<Vec<u8>> = reconstruct_secret(&shares[0..k as usize]).unwrap();
         assert_eq!(secret, reconstructed.as_slice());
         
         // Test with all shares
@@ -80,6 +706,79 @@ mod tests {
         assert_eq!(secret, reconstructed.as_slice());
     }
     
+    /// The secret at x = 0 of the polynomials through `shares`, worked out
+    /// independently of [`combine`].
+    fn interpolate(shares: &[Vec<u8>]) -> Vec<u8> {
+        let inverse = |a: u8| (0..253).fold(a, |power, _| seeded::gf_mul(power, a));
+        let len = shares[0].len() - 1;
+        (0..len)
+            .map(|i| {
+                shares.iter().fold(0, |secret, share| {
+                    let x = share[len];
+                    let basis = shares
+                        .iter()
+                        .map(|other| other[len])
+                        .filter(|&other| other != x)
+                        .fold(1, |basis, other| seeded::gf_mul(basis, seeded::gf_mul(other, inverse(other ^ x))));
+                    secret ^ seeded::gf_mul(share[i], basis)
+                })
+            })
+            .collect()
+    }
+
+    /// `b"golden"` split into three shares, two of them needed, with
+    /// `StdRng::seed_from_u64(7)`.
+    const KNOWN_SHARES: &[&[u8]] = &[
+        &[110, 161, 232, 0, 221, 161, 216],
+        &[0, 228, 207, 2, 70, 118, 63],
+        &[161, 145, 30, 142, 230, 227, 190],
+    ];
+
+    #[test]
+    fn test_seeded_split_matches_shamirs() {
+        use rand::{rngs::StdRng, SeedableRng};
+
+        let split_seeded = |seed| {
+            seeded::split_secret_for_file(b"secret", 2, 3, &[], "file-1", &mut StdRng::seed_from_u64(seed)).unwrap()
+        };
+        assert_eq!(split_seeded(1), split_seeded(1));
+        assert_ne!(split_seeded(1), split_seeded(2));
+        assert_eq!(reconstruct_secret(&split_seeded(1)[1..]).unwrap().as_slice(), b"secret");
+        assert!(matches!(split_secret(b"secret", 1, 3), Err(SSSError::InvalidThreshold)));
+
+        // The field is the one combine works in
+        assert_eq!(seeded::gf_mul(0x53, 0xCA), 0x01);
+        assert_eq!(seeded::gf_mul(0x57, 0x83), 0xC1);
+
+        // Known answer: these shares come back through combine, and so does
+        // the same secret split by the shamirs crate through interpolate
+        let secret = b"golden";
+        let shares = seeded::split(secret, 3, 2, &mut StdRng::seed_from_u64(7)).unwrap();
+        assert_eq!(shares.as_slice(), KNOWN_SHARES);
+        for pair in [[0, 1], [0, 2], [1, 2]] {
+            let pair = [shares[pair[0]].clone(), shares[pair[1]].clone()];
+            assert_eq!(combine(&pair).unwrap(), secret);
+            assert_eq!(interpolate(&pair), secret);
+        }
+        let shares = split(secret, 5, 3).unwrap();
+        assert!(shares.iter().all(|share| share.len() == secret.len() + 1));
+        assert_eq!(interpolate(&shares[..3]), secret);
+        assert_eq!(interpolate(&shares[2..]), secret);
+    }
+
+    #[test]
+    fn test_combine_shares() {
+        let secret = [7u8; 32];
+        let shares: Vec<String> = split_secret(&secret, 2, 3).unwrap().iter().map(LabeledShare::encode).collect();
+        
+        for pair in [&shares[..2], &shares[1..]] {
+            let combined = combine_shares(pair).unwrap();
+            assert_eq!(general_purpose::STANDARD.decode(combined.as_str()).unwrap(), secret);
+        }
+        assert!(matches!(combine_shares(&shares[..1]), Err(SSSError::InsufficientShares)));
+        assert!(combine_shares(&["not a share".to_string()]).is_err());
+    }
+    
     #[test]
     fn test_insufficient_shares() {
         let secret = b"secret";
@@ -90,4 +789,193 @@ mod tests {
         // With the shamirs crate, this should properly fail
         assert!(result.is_err(), "Should fail with insufficient shares");
     }
-} 
\ No newline at end of file
+
+    #[test]
+    fn test_labels_and_encoding() {
+        let labels = vec!["Alice".to_string(), "Bob: backup".to_string(), "Carol".to_string()];
+        let shares = split_secret_labeled(b"secret", 2, 3, &labels).unwrap();
+        assert_eq!(shares[1].index, 2);
+        assert_eq!(shares[1].label, "Bob: backup");
+        assert_eq!((shares[1].threshold, shares[1].total), (2, 3));
+
+        for share in &shares {
+            assert_eq!(&LabeledShare::decode(&share.encode()).unwrap(), share);
+        }
+
+        let unlabeled = split_secret(b"secret", 2, 3).unwrap();
+        assert_eq!(unlabeled[2].label, "Share 3");
+
+        // Labels are for display only; renaming a share doesn't affect it
+        let mut renamed = shares[..2].to_vec();
+        renamed[0].label = "Alice (safe deposit box)".to_string();
+        renamed[1].label.clear();
+        assert_eq!(reconstruct_secret(&renamed).unwrap().as_slice(), b"secret");
+
+        assert!(matches!(
+            split_secret_labeled(b"secret", 2, 3, &labels[..2]),
+            Err(SSSError::LabelCountMismatch { expected: 3, actual: 2 })
+        ));
+        let bad = vec!["Alice".to_string(), "Bob\n".to_string(), "Carol".to_string()];
+        assert!(matches!(split_secret_labeled(b"secret", 2, 3, &bad), Err(SSSError::InvalidLabel(_))));
+    }
+
+    #[test]
+    fn test_inconsistent_metadata() {
+        let shares = split_secret(b"secret", 2, 3).unwrap();
+        let mut tampered = shares[1].clone();
+        tampered.total = 4;
+        let result = reconstruct_secret(&[shares[0].clone(), tampered]);
+        assert!(matches!(result, Err(SSSError::InconsistentMetadata)));
+    }
+
+    #[test]
+    fn test_validate_shares() {
+        let encode = |shares: &[LabeledShare]| shares.iter().map(LabeledShare::encode).collect::<Vec<_>>();
+        let three_of_five = encode(&split_secret(b"secret", 3, 5).unwrap());
+        let two_of_three = encode(&split_secret(b"secret", 2, 3).unwrap());
+
+        assert_eq!(validate_shares(&three_of_five[1..4]).unwrap().len(), 3);
+        assert!(matches!(validate_shares(&three_of_five[..2]), Err(SSSError::InsufficientShares)));
+        assert!(matches!(validate_shares(&[]), Err(SSSError::InsufficientShares)));
+
+        let mixed = vec![three_of_five[0].clone(), three_of_five[1].clone(), two_of_three[2].clone()];
+        assert!(matches!(validate_shares(&mixed), Err(SSSError::InconsistentMetadata)));
+    }
+
+    #[test]
+    fn test_corrupted_share_is_reported() {
+        let shares = split_secret(b"secret", 2, 3).unwrap();
+        let hmac_key = dealer_hmac_key(&shares[0]);
+        assert!(shares.iter().all(|share| verify_share_integrity(share, &hmac_key)));
+
+        let mut raw = general_purpose::STANDARD.decode(&shares[1].data).unwrap();
+        raw[0] ^= 1;
+        let mut corrupted = shares[1].clone();
+        corrupted.data = general_purpose::STANDARD.encode(raw);
+        assert!(!verify_share_integrity(&corrupted, &hmac_key));
+        assert!(matches!(
+            reconstruct_secret(&[shares[0].clone(), corrupted]),
+            Err(SSSError::CorruptedShare { index: 2 })
+        ));
+
+        // Renumbering a share invalidates its MAC too
+        let mut renumbered = shares[2].clone();
+        renumbered.index = 1;
+        assert!(!verify_share_integrity(&renumbered, &hmac_key));
+    }
+
+    #[test]
+    fn test_duplicate_share() {
+        let shares = split_secret(b"secret", 2, 3).unwrap();
+        let result = reconstruct_secret(&[shares[0].clone(), shares[0].clone()]);
+        assert!(matches!(result, Err(SSSError::DuplicateShare { index: 1 })));
+
+        // Still caught when the copy was relabelled
+        let mut relabelled = shares[1].clone();
+        relabelled.label = "Backup".to_string();
+        let result = reconstruct_secret(&[shares[1].clone(), shares[0].clone(), relabelled]);
+        assert!(matches!(result, Err(SSSError::DuplicateShare { index: 2 })));
+
+        // Bare shares are named by the order they were entered in
+        let bare: Vec<LabeledShare> = shares
+            .iter()
+            .map(|share| {
+                let mut raw = general_purpose::STANDARD.decode(&share.data).unwrap();
+                raw.truncate(raw.len() - SHARE_MAC_LEN);
+                LabeledShare::decode(&general_purpose::STANDARD.encode(raw)).unwrap()
+            })
+            .collect();
+        let result = reconstruct_secret(&[bare[2].clone(), bare[0].clone(), bare[2].clone()]);
+        assert!(matches!(result, Err(SSSError::DuplicateShare { index: 3 })));
+        assert_eq!(result.unwrap_err().to_string(), "Share 3 was entered more than once");
+    }
+
+    #[test]
+    fn test_shares_bound_to_file() {
+        let shares = split_secret_for_file(b"secret", 2, 3, &[], "file-a").unwrap();
+        let other = split_secret_for_file(b"secret", 2, 3, &[], "file-b").unwrap();
+        let encoded = shares[1].encode();
+        assert!(encoded.starts_with("CSHR2:2:2:3:file-a:Share 2:"));
+        assert_eq!(LabeledShare::decode(&encoded).unwrap(), shares[1]);
+
+        assert!(check_file_id(&shares, "file-a").is_ok());
+        let mixed = [shares[0].clone(), other[1].clone()];
+        assert!(matches!(check_file_id(&mixed, "file-a"), Err(SSSError::WrongFile { position: 2 })));
+
+        // Shares without an ID are left to the key check
+        let unbound = split_secret(b"secret", 2, 3).unwrap();
+        assert!(check_file_id(&unbound, "file-a").is_ok());
+
+        // The ID is covered by the share MAC
+        let mut moved = shares[0].clone();
+        moved.file_id = Some("file-b".to_string());
+        assert!(!verify_share_integrity(&moved, &dealer_hmac_key(&moved)));
+    }
+
+    #[test]
+    fn test_bare_shares_still_accepted() {
+        let shares = split_secret(b"secret", 2, 3).unwrap();
+        let bare: Vec<String> = shares
+            .iter()
+            .map(|share| {
+                let raw = general_purpose::STANDARD.decode(&share.data).unwrap();
+                general_purpose::STANDARD.encode(&raw[..raw.len() - SHARE_MAC_LEN])
+            })
+            .collect();
+        let decoded = decode_shares(&bare[..2]).unwrap();
+        assert_eq!(decoded[0].threshold, 0);
+        assert_eq!(reconstruct_secret(&decoded).unwrap().as_slice(), b"secret");
+    }
+
+    /// Every `k`-element subset of `0..n`, in lexicographic order.
+    fn combinations(n: usize, k: usize) -> Vec<Vec<usize>> {
+        if k == 0 {
+            return vec![Vec::new()];
+        }
+        (k - 1..n)
+            .flat_map(|last| {
+                combinations(last, k - 1).into_iter().map(move |mut subset| {
+                    subset.push(last);
+                    subset
+                })
+            })
+            .collect()
+    }
+
+    /// A split with `2 <= k <= n <= 10`; the shamirs crate needs at least
+    /// two shares to interpolate.
+    fn threshold_and_total() -> impl Strategy<Value = (u8, u8)> {
+        (2u8..=10).prop_flat_map(|n| (2..=n, Just(n)))
+    }
+
+    proptest! {
+        // Each case tries every subset, up to 252 of them
+        #![proptest_config(ProptestConfig::with_cases(32))]
+
+        #[test]
+        fn prop_any_k_shares_reconstruct(
+            secret in prop::collection::vec(any::<u8>(), 1..=256),
+            (k, n) in threshold_and_total(),
+        ) {
+            let shares = split_secret(&secret, k, n).unwrap();
+            for subset in combinations(n as usize, k as usize) {
+                let chosen: Vec<LabeledShare> = subset.iter().map(|&i| shares[i].clone()).collect();
+                let recovered = reconstruct_secret(&chosen).unwrap();
+                prop_assert_eq!(recovered.as_slice(), secret.as_slice());
+            }
+        }
+
+        #[test]
+        fn prop_fewer_than_k_shares_never_reconstruct(
+            secret in prop::collection::vec(any::<u8>(), 1..=256),
+            (k, n) in threshold_and_total(),
+        ) {
+            let shares = split_secret(&secret, k, n).unwrap();
+            for subset in combinations(n as usize, k as usize - 1) {
+                let chosen: Vec<LabeledShare> = subset.iter().map(|&i| shares[i].clone()).collect();
+                let result = reconstruct_secret(&chosen);
+                prop_assert!(!matches!(&result, Ok(recovered) if recovered.as_slice() == secret.as_slice()));
+            }
+        }
+    }
+}